@@ -0,0 +1,333 @@
+//! Ambient chatter: in channels that opted in with `/channel ambient`, Nuggies now and then
+//! chimes in on an ongoing conversation without being asked, using the last few messages as
+//! context. Each message has a configurable chance of waking her, and after speaking she
+//! waits out the channel's cooldown. She stays quiet in channels marked serious with
+//! `/channel serious`, for a while after moderators act in the server (bans, timeouts, bulk
+//! deletes), and whenever the model decides it has nothing worth adding.
+
+use crate::prompts::Prompts;
+use crate::usage::Caller;
+use crate::{call_llm, persona, reply, safety, AmbientKey, BotConfigKey, BotUserIdKey, Database, DatabaseKey, LlmProviderKey, PromptsKey};
+use bb8::RunError;
+use chrono::Utc;
+use rand::Rng;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio_postgres::types::ToSql;
+
+pub const DEFAULT_CHANCE_PERCENT: i64 = 5;
+pub const MAX_CHANCE_PERCENT: i64 = 50;
+pub const DEFAULT_COOLDOWN_MINUTES: i64 = 30;
+pub const MAX_COOLDOWN_MINUTES: i64 = 1440;
+/// How many recent messages are shown to the model.
+const CONTEXT_MESSAGES: u64 = 10;
+const MAX_MESSAGE_CHARS: usize = 300;
+/// Only messages this recent count as an ongoing conversation.
+const CONVERSATION_WINDOW_SECS: i64 = 10 * 60;
+/// A conversation needs at least this many people in it; she doesn't butt into monologues.
+const MIN_PARTICIPANTS: usize = 2;
+/// How long the whole server stays quiet after a moderation action.
+const MODERATION_QUIET: Duration = Duration::from_secs(15 * 60);
+/// The model answers with this when it has nothing to add.
+const SKIP: &str = "SKIP";
+
+/// When each channel last heard from Nuggies unprompted and when each guild last saw a
+/// moderation action. Kept in memory; a restart only means she may speak up a bit sooner.
+/// Entries are dropped once they can no longer matter, so the maps only hold channels and
+/// guilds that are still cooling down.
+#[derive(Default)]
+pub struct AmbientState {
+    last_chime: Mutex<HashMap<u64, Instant>>,
+    last_moderation: Mutex<HashMap<u64, Instant>>,
+}
+
+/// No channel's cooldown is longer than this.
+const MAX_COOLDOWN: Duration = Duration::from_secs(MAX_COOLDOWN_MINUTES as u64 * 60);
+
+impl AmbientState {
+    pub fn record_moderation(&self, guild_id: GuildId) {
+        let mut last_moderation = self.last_moderation.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        last_moderation.retain(|_, at| at.elapsed() < MODERATION_QUIET);
+        last_moderation.insert(guild_id.0, Instant::now());
+    }
+
+    fn moderation_recent(&self, guild_id: GuildId) -> bool {
+        let mut last_moderation = self.last_moderation.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match last_moderation.get(&guild_id.0) {
+            Some(at) if at.elapsed() < MODERATION_QUIET => true,
+            Some(_) => {
+                last_moderation.remove(&guild_id.0);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn on_cooldown(&self, channel_id: ChannelId, cooldown: Duration) -> bool {
+        self.last_chime.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(&channel_id.0).is_some_and(|at| at.elapsed() < cooldown)
+    }
+
+    /// Starts the channel's cooldown, `false` if it already started (another message got
+    /// there first).
+    fn take_turn(&self, channel_id: ChannelId, cooldown: Duration) -> bool {
+        let mut last_chime = self.last_chime.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if last_chime.get(&channel_id.0).is_some_and(|at| at.elapsed() < cooldown) {
+            return false;
+        }
+        last_chime.retain(|_, at| at.elapsed() < MAX_COOLDOWN);
+        last_chime.insert(channel_id.0, Instant::now());
+        true
+    }
+}
+
+pub struct ChannelConfig {
+    pub chance_percent: i64,
+    pub cooldown_minutes: i64,
+}
+
+/// The channel's ambient chatter settings, `None` if it is off or the channel is serious.
+async fn get_config(db: &Database, channel_id: ChannelId) -> Option<ChannelConfig> {
+    let conn = match db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection for ambient chatter lookup: {:?}", e);
+            return None;
+        }
+    };
+    let channel_id_i64 = channel_id.0 as i64;
+    match conn.query_opt(
+        "SELECT chance_percent, cooldown_minutes FROM ambient_channels a
+         WHERE channel_id = $1 AND NOT EXISTS (SELECT 1 FROM serious_channels s WHERE s.channel_id = a.channel_id)",
+        &[&channel_id_i64],
+    ).await {
+        Ok(row) => row.map(|r| ChannelConfig { chance_percent: r.get::<_, i32>(0) as i64, cooldown_minutes: r.get::<_, i32>(1) as i64 }),
+        Err(e) => {
+            eprintln!("[ERROR] Failed to load ambient chatter setup for channel (ID: {}): {:?}", channel_id, e);
+            None
+        }
+    }
+}
+
+async fn is_serious(db: &Database, channel_id: ChannelId) -> Result<bool, RunError<tokio_postgres::Error>> {
+    let conn = db.pool.get().await?;
+    let channel_id_i64 = channel_id.0 as i64;
+    Ok(conn.query_opt("SELECT 1 FROM serious_channels WHERE channel_id = $1", &[&channel_id_i64]).await?.is_some())
+}
+
+/// Turns ambient chatter on (or changes its settings) for a channel, returning the reply
+/// for the admin.
+pub async fn enable(db: &Database, guild_id: GuildId, channel_id: ChannelId, chance_percent: i64, cooldown_minutes: i64, updated_by: u64) -> String {
+    match is_serious(db, channel_id).await {
+        Ok(true) => return "This channel is marked serious, so I keep quiet here. Use `/channel serious enabled:False` first.".to_string(),
+        Ok(false) => {}
+        Err(e) => {
+            eprintln!("[ERROR] Failed to check whether channel (ID: {}) is serious: {:?}", channel_id, e);
+            return "Sorry, I couldn't save that right now.".to_string();
+        }
+    }
+
+    let chance_percent = chance_percent.clamp(1, MAX_CHANCE_PERCENT) as i32;
+    let cooldown_minutes = cooldown_minutes.clamp(1, MAX_COOLDOWN_MINUTES) as i32;
+    let conn = match db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection to save ambient chatter setup: {:?}", e);
+            return "Sorry, I couldn't save that right now.".to_string();
+        }
+    };
+    let channel_id_i64 = channel_id.0 as i64;
+    let guild_id_i64 = guild_id.0 as i64;
+    let updated_by_i64 = updated_by as i64;
+    let params: &[&(dyn ToSql + Sync)] = &[&channel_id_i64, &guild_id_i64, &chance_percent, &cooldown_minutes, &updated_by_i64];
+    match conn.execute(
+        "INSERT INTO ambient_channels (channel_id, guild_id, chance_percent, cooldown_minutes, updated_by, updated_at)
+         VALUES ($1, $2, $3, $4, $5, NOW())
+         ON CONFLICT (channel_id) DO UPDATE SET chance_percent = EXCLUDED.chance_percent, cooldown_minutes = EXCLUDED.cooldown_minutes,
+         updated_by = EXCLUDED.updated_by, updated_at = NOW()",
+        params,
+    ).await {
+        Ok(_) => {
+            println!("[ACTION] User (ID: {}) turned on ambient chatter ({}%, {} min) for channel (ID: {}).", updated_by, chance_percent, cooldown_minutes, channel_id);
+            format!(
+                "Done. I'll chime in on conversations here now and then ({}% chance per message, at most once every {} minutes).",
+                chance_percent, cooldown_minutes
+            )
+        }
+        Err(e) => {
+            eprintln!("[ERROR] Failed to save ambient chatter setup for channel (ID: {}): {:?}", channel_id, e);
+            "Sorry, I couldn't save that right now.".to_string()
+        }
+    }
+}
+
+/// Turns ambient chatter off for a channel, returning the reply for the admin.
+pub async fn disable(db: &Database, channel_id: ChannelId) -> String {
+    let conn = match db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection to remove ambient chatter setup: {:?}", e);
+            return "Sorry, I couldn't turn that off right now.".to_string();
+        }
+    };
+    let channel_id_i64 = channel_id.0 as i64;
+    match conn.execute("DELETE FROM ambient_channels WHERE channel_id = $1", &[&channel_id_i64]).await {
+        Ok(0) => "I don't chime in on my own in this channel anyway.".to_string(),
+        Ok(_) => {
+            println!("[ACTION] Turned off ambient chatter for channel (ID: {}).", channel_id);
+            "Done. I'll only speak here when spoken to.".to_string()
+        }
+        Err(e) => {
+            eprintln!("[ERROR] Failed to remove ambient chatter setup for channel (ID: {}): {:?}", channel_id, e);
+            "Sorry, I couldn't turn that off right now.".to_string()
+        }
+    }
+}
+
+/// Marks a channel as serious or not, returning the reply for the admin. Serious channels
+/// never get ambient chatter, whatever their settings say.
+pub async fn set_serious(db: &Database, guild_id: GuildId, channel_id: ChannelId, serious: bool, updated_by: u64) -> String {
+    let conn = match db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection to save serious channel setting: {:?}", e);
+            return "Sorry, I couldn't save that right now.".to_string();
+        }
+    };
+    let channel_id_i64 = channel_id.0 as i64;
+    let result = if serious {
+        let guild_id_i64 = guild_id.0 as i64;
+        let updated_by_i64 = updated_by as i64;
+        let params: &[&(dyn ToSql + Sync)] = &[&channel_id_i64, &guild_id_i64, &updated_by_i64];
+        conn.execute(
+            "INSERT INTO serious_channels (channel_id, guild_id, updated_by, updated_at) VALUES ($1, $2, $3, NOW())
+             ON CONFLICT (channel_id) DO UPDATE SET updated_by = EXCLUDED.updated_by, updated_at = NOW()",
+            params,
+        ).await
+    } else {
+        conn.execute("DELETE FROM serious_channels WHERE channel_id = $1", &[&channel_id_i64]).await
+    };
+
+    match result {
+        Ok(_) => {
+            println!("[ACTION] User (ID: {}) marked channel (ID: {}) as {}.", updated_by, channel_id, if serious { "serious" } else { "not serious" });
+            if serious {
+                "Done. This channel is serious business, so I won't chime in here on my own.".to_string()
+            } else {
+                "Done. This channel isn't marked serious anymore.".to_string()
+            }
+        }
+        Err(e) => {
+            eprintln!("[ERROR] Failed to save serious setting for channel (ID: {}): {:?}", channel_id, e);
+            "Sorry, I couldn't save that right now.".to_string()
+        }
+    }
+}
+
+/// The recent messages before and including `msg`, oldest first, if they make up a
+/// conversation between at least `MIN_PARTICIPANTS` people.
+async fn recent_conversation(ctx: &Context, msg: &Message) -> Option<Vec<Message>> {
+    let mut messages = match msg.channel_id.messages(&ctx.http, |request| request.before(msg.id).limit(CONTEXT_MESSAGES - 1)).await {
+        Ok(messages) => messages,
+        Err(e) => {
+            eprintln!("[ERROR] Could not fetch recent messages in channel (ID: {}): {:?}", msg.channel_id, e);
+            return None;
+        }
+    };
+    messages.reverse();
+    messages.push(msg.clone());
+
+    let since = Utc::now().timestamp() - CONVERSATION_WINDOW_SECS;
+    messages.retain(|message| message.timestamp.unix_timestamp() >= since && !message.content.trim().is_empty());
+    let participants: HashSet<UserId> = messages.iter().filter(|message| !message.author.bot).map(|message| message.author.id).collect();
+    if participants.len() < MIN_PARTICIPANTS { None } else { Some(messages) }
+}
+
+fn render_conversation(prompts: &Prompts, messages: &[Message], bot_id: UserId) -> String {
+    let lines: Vec<String> = messages.iter().map(|message| {
+        let author = if message.author.id == bot_id { "Nuggies" } else { message.author.name.as_str() };
+        let content: String = message.content.chars().take(MAX_MESSAGE_CHARS).collect();
+        format!("{}: {}", author, content)
+    }).collect();
+    prompts.render("ambient", &[("conversation", &safety::delimit_user_text(&lines.join("\n")))])
+}
+
+/// Maybe chimes in on the conversation `msg` belongs to, if its channel has ambient chatter on.
+pub async fn handle_message(ctx: &Context, msg: &Message) {
+    let Some(guild_id) = msg.guild_id else { return };
+    let (db, state, llm, prompts, max_chunks, bot_id, caller) = {
+        let data = ctx.data.read().await;
+        let Some(bot_id) = data.get::<BotUserIdKey>().copied() else { return };
+        (
+            data.get::<DatabaseKey>().expect("Expected DatabaseKey in TypeMap.").clone(),
+            data.get::<AmbientKey>().expect("Expected AmbientKey in TypeMap.").clone(),
+            data.get::<LlmProviderKey>().expect("Expected LlmProviderKey in TypeMap.").clone(),
+            data.get::<PromptsKey>().expect("Expected PromptsKey in TypeMap.").clone(),
+            data.get::<BotConfigKey>().expect("Expected BotConfigKey in TypeMap.").max_reply_chunks,
+            bot_id,
+            // Nobody asked, so the tokens count against the guild's quota only, under the bot's own name.
+            Caller::for_guild(&data, bot_id, guild_id, "ambient"),
+        )
+    };
+    let Some(config) = get_config(&db, msg.channel_id).await else { return };
+    if rand::thread_rng().gen_range(0..100) >= config.chance_percent {
+        return;
+    }
+
+    let cooldown = Duration::from_secs(config.cooldown_minutes as u64 * 60);
+    if state.on_cooldown(msg.channel_id, cooldown) {
+        return;
+    }
+    if state.moderation_recent(guild_id) {
+        println!("[AMBIENT] Staying quiet in channel (ID: {}) after recent moderation in Guild (ID: {}).", msg.channel_id, guild_id);
+        return;
+    }
+    let Some(messages) = recent_conversation(ctx, msg).await else { return };
+    if !state.take_turn(msg.channel_id, cooldown) {
+        return;
+    }
+
+    println!("[AMBIENT] Chiming in on the conversation in channel (ID: {}).", msg.channel_id);
+    let personality = persona::get_personality(&db, Some(guild_id)).await;
+    let answer = match call_llm(llm.as_ref(), &caller, Some(&personality), &[], &render_conversation(&prompts, &messages, bot_id)).await {
+        Ok(answer) => answer,
+        Err(e) => {
+            eprintln!("[ERROR] Ambient chatter in channel (ID: {}) failed: {}", msg.channel_id, e);
+            return;
+        }
+    };
+    let answer = answer.trim();
+    if answer.is_empty() || answer.trim_matches(|c: char| !c.is_alphanumeric()).eq_ignore_ascii_case(SKIP) {
+        println!("[AMBIENT] Nothing to add in channel (ID: {}).", msg.channel_id);
+        return;
+    }
+    reply::send_channel_reply(ctx, msg.channel_id, answer, max_chunks).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let state = AmbientState::default();
+        // The monotonic clock may not reach back a day on a freshly booted machine.
+        let Some(long_ago) = Instant::now().checked_sub(MAX_COOLDOWN + MODERATION_QUIET) else { return };
+        state.last_chime.lock().unwrap().insert(1, long_ago);
+        state.last_moderation.lock().unwrap().insert(2, long_ago);
+        state.last_moderation.lock().unwrap().insert(3, long_ago);
+
+        assert!(state.take_turn(ChannelId(4), Duration::from_secs(60)));
+        assert!(!state.last_chime.lock().unwrap().contains_key(&1));
+
+        assert!(!state.moderation_recent(GuildId(2)));
+        assert!(!state.last_moderation.lock().unwrap().contains_key(&2));
+        state.record_moderation(GuildId(5));
+        assert!(!state.last_moderation.lock().unwrap().contains_key(&3));
+        assert!(state.moderation_recent(GuildId(5)));
+    }
+}
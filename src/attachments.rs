@@ -0,0 +1,99 @@
+//! Turning Discord message attachments into inline data the model can look at.
+
+use crate::config::BotConfig;
+use crate::llm::ChatPart;
+use serenity::model::channel::Attachment;
+
+/// Image types Gemini accepts as inline data.
+const IMAGE_TYPES: &[&str] = &["image/png", "image/jpeg", "image/webp", "image/heic", "image/heif"];
+/// Document types accepted when `attachment_documents` is enabled.
+const DOCUMENT_TYPES: &[&str] = &["application/pdf", "text/plain", "text/markdown", "text/csv"];
+
+/// Discord's content type, without parameters like "; charset=utf-8", or a guess from the
+/// file extension when Discord didn't send one.
+fn mime_type(attachment: &Attachment) -> Option<String> {
+    if let Some(content_type) = &attachment.content_type {
+        return content_type.split(';').next().map(|t| t.trim().to_lowercase());
+    }
+    let extension = attachment.filename.rsplit('.').next()?.to_lowercase();
+    let guess = match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "webp" => "image/webp",
+        "heic" => "image/heic",
+        "heif" => "image/heif",
+        "pdf" => "application/pdf",
+        "txt" | "log" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        _ => return None,
+    };
+    Some(guess.to_string())
+}
+
+fn is_supported(mime_type: &str, config: &BotConfig) -> bool {
+    IMAGE_TYPES.contains(&mime_type) || (config.attachment_documents && DOCUMENT_TYPES.contains(&mime_type))
+}
+
+fn supported_description(config: &BotConfig) -> &'static str {
+    if config.attachment_documents {
+        "images (PNG, JPEG, WebP, HEIC), PDFs and plain text files"
+    } else {
+        "images (PNG, JPEG, WebP, HEIC)"
+    }
+}
+
+/// Downloads the attachments of a message as inline data parts. If any of them can't be
+/// used, nothing is downloaded and `Err` holds a refusal that can be posted as-is.
+pub async fn collect_inline_parts(attachments: &[Attachment], config: &BotConfig) -> Result<Vec<ChatPart>, String> {
+    if attachments.len() > config.max_attachments {
+        return Err(format!(
+            "That's a lot of files. I can only look at {} attachments per message.",
+            config.max_attachments
+        ));
+    }
+
+    let mut accepted = Vec::new();
+    for attachment in attachments {
+        let mime_type = match mime_type(attachment) {
+            Some(mime_type) if is_supported(&mime_type, config) => mime_type,
+            other => {
+                return Err(format!(
+                    "I can't look at `{}` ({}). I only understand {}.",
+                    attachment.filename,
+                    other.unwrap_or_else(|| "unknown type".to_string()),
+                    supported_description(config)
+                ));
+            }
+        };
+        if attachment.size > config.max_attachment_bytes {
+            return Err(format!(
+                "`{}` is too big for me ({:.1} MB). Keep attachments under {:.1} MB.",
+                attachment.filename,
+                attachment.size as f64 / 1_048_576.0,
+                config.max_attachment_bytes as f64 / 1_048_576.0
+            ));
+        }
+        accepted.push((attachment, mime_type));
+    }
+
+    let mut parts = Vec::new();
+    for (attachment, mime_type) in accepted {
+        match attachment.download().await {
+            Ok(data) => {
+                println!("[ACTION] Downloaded attachment '{}' ({}, {} bytes).", attachment.filename, mime_type, data.len());
+                parts.push(ChatPart::InlineData { mime_type, data });
+            }
+            Err(e) => {
+                eprintln!("[ERROR] Could not download attachment '{}': {:?}", attachment.filename, e);
+                return Err(format!("I couldn't download `{}`. Try sending it again?", attachment.filename));
+            }
+        }
+    }
+    Ok(parts)
+}
+
+/// How an attachment is remembered in the conversation history, which only stores text.
+pub fn history_note(attachments: &[Attachment]) -> String {
+    attachments.iter().map(|a| format!(" [attached: {}]", a.filename)).collect()
+}
@@ -0,0 +1,296 @@
+//! Auto-translate channels: every message in a channel set up with `/translation auto` is
+//! translated into the channel's languages and posted underneath, either as a reply or
+//! through a webhook that looks like the author. Languages the message is already written
+//! in are skipped.
+
+use crate::llm::{LlmError, LlmProvider};
+use crate::prompts::Prompts;
+use crate::reply::{self, DISCORD_MESSAGE_LIMIT};
+use crate::safety;
+use crate::usage::Caller;
+use crate::{call_llm_structured, BotConfigKey, CooldownsKey, Database, DatabaseKey, LlmProviderKey, PromptsKey};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId};
+use tokio_postgres::types::ToSql;
+
+const MAX_LANGUAGES: usize = 5;
+const WEBHOOK_NAME: &str = "Nuggies Translate";
+/// Discord's limit for webhook usernames.
+const MAX_USERNAME_CHARS: usize = 80;
+
+pub struct ChannelConfig {
+    pub languages: Vec<String>,
+    /// ID and token of the channel's webhook, if translations are posted through one.
+    pub webhook: Option<(u64, String)>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Translations {
+    #[serde(default)]
+    source_language: Option<String>,
+    #[serde(default)]
+    translations: Vec<TranslatedText>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TranslatedText {
+    language: String,
+    text: String,
+}
+
+fn schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "source_language": { "type": "string", "description": "The language the original text is written in, in English, e.g. \"German\"." },
+            "translations": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "language": { "type": "string", "description": "The target language, exactly as it was given." },
+                        "text": { "type": "string", "description": "The text translated into that language." }
+                    },
+                    "required": ["language", "text"]
+                }
+            }
+        },
+        "required": ["source_language", "translations"]
+    })
+}
+
+/// Splits "English, German" into at most `MAX_LANGUAGES` distinct languages.
+pub fn parse_languages(text: &str) -> Vec<String> {
+    let mut languages: Vec<String> = Vec::new();
+    for language in text.split(',').map(|l| safety::single_line(l.trim(), 50)).filter(|l| !l.is_empty()) {
+        if !languages.iter().any(|known| known.eq_ignore_ascii_case(&language)) {
+            languages.push(language);
+        }
+    }
+    languages.truncate(MAX_LANGUAGES);
+    languages
+}
+
+/// The channel's auto-translate setup, if it has one.
+pub async fn get_config(db: &Database, channel_id: ChannelId) -> Option<ChannelConfig> {
+    let conn = match db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection for auto-translate lookup: {:?}", e);
+            return None;
+        }
+    };
+    let channel_id_i64 = channel_id.0 as i64;
+    match conn.query_opt(
+        "SELECT languages, webhook_id, webhook_token FROM auto_translate_channels WHERE channel_id = $1",
+        &[&channel_id_i64],
+    ).await {
+        Ok(row) => row.map(|r| {
+            let languages: String = r.get(0);
+            let webhook_id: Option<i64> = r.get(1);
+            let webhook_token: Option<String> = r.get(2);
+            ChannelConfig {
+                languages: parse_languages(&languages),
+                webhook: webhook_id.zip(webhook_token).map(|(id, token)| (id as u64, token)),
+            }
+        }),
+        Err(e) => {
+            eprintln!("[ERROR] Failed to load auto-translate setup for channel (ID: {}): {:?}", channel_id, e);
+            None
+        }
+    }
+}
+
+/// Sets up (or changes) auto-translation for a channel, returning the reply for the admin.
+pub async fn enable(ctx: &Context, db: &Database, guild_id: GuildId, channel_id: ChannelId, languages: &str, use_webhook: bool, updated_by: u64) -> String {
+    let languages = parse_languages(languages);
+    if languages.is_empty() {
+        return "Tell me at least one language to translate into, e.g. `English, German`.".to_string();
+    }
+
+    let previous = get_config(db, channel_id).await.and_then(|config| config.webhook);
+    let webhook = match (use_webhook, previous) {
+        (true, Some(webhook)) => Some(webhook),
+        (true, None) => match channel_id.create_webhook(&ctx.http, WEBHOOK_NAME).await {
+            Ok(webhook) => webhook.token.map(|token| (webhook.id.0, token)),
+            Err(e) => {
+                eprintln!("[ERROR] Could not create webhook in channel (ID: {}): {:?}", channel_id, e);
+                return "I couldn't create a webhook here. Give me the Manage Webhooks permission, or leave out `webhook`.".to_string();
+            }
+        },
+        (false, previous) => {
+            if let Some((id, token)) = previous {
+                delete_webhook(ctx, id, &token).await;
+            }
+            None
+        }
+    };
+
+    let conn = match db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection to save auto-translate setup: {:?}", e);
+            return "Sorry, I couldn't save that right now.".to_string();
+        }
+    };
+    let guild_id_i64 = guild_id.0 as i64;
+    let channel_id_i64 = channel_id.0 as i64;
+    let languages_text = languages.join(", ");
+    let webhook_id = webhook.as_ref().map(|(id, _)| *id as i64);
+    let webhook_token = webhook.as_ref().map(|(_, token)| token.clone());
+    let updated_by_i64 = updated_by as i64;
+    let params: &[&(dyn ToSql + Sync)] = &[&channel_id_i64, &guild_id_i64, &languages_text, &webhook_id, &webhook_token, &updated_by_i64];
+    match conn.execute(
+        "INSERT INTO auto_translate_channels (channel_id, guild_id, languages, webhook_id, webhook_token, updated_by, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, NOW())
+         ON CONFLICT (channel_id) DO UPDATE SET languages = EXCLUDED.languages, webhook_id = EXCLUDED.webhook_id,
+         webhook_token = EXCLUDED.webhook_token, updated_by = EXCLUDED.updated_by, updated_at = NOW()",
+        params,
+    ).await {
+        Ok(_) => {
+            println!("[ACTION] User (ID: {}) set up auto-translation into {} for channel (ID: {}).", updated_by, languages_text, channel_id);
+            format!(
+                "Done. I'll translate every message in this channel into {}{}.",
+                languages_text, if webhook.is_some() { ", posted under the author's name" } else { "" }
+            )
+        }
+        Err(e) => {
+            eprintln!("[ERROR] Failed to save auto-translate setup for channel (ID: {}): {:?}", channel_id, e);
+            "Sorry, I couldn't save that right now.".to_string()
+        }
+    }
+}
+
+/// Turns auto-translation off for a channel, returning the reply for the admin.
+pub async fn disable(ctx: &Context, db: &Database, channel_id: ChannelId) -> String {
+    let webhook = get_config(db, channel_id).await.and_then(|config| config.webhook);
+    let conn = match db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection to remove auto-translate setup: {:?}", e);
+            return "Sorry, I couldn't turn that off right now.".to_string();
+        }
+    };
+    let channel_id_i64 = channel_id.0 as i64;
+    match conn.execute("DELETE FROM auto_translate_channels WHERE channel_id = $1", &[&channel_id_i64]).await {
+        Ok(0) => "This channel isn't auto-translated.".to_string(),
+        Ok(_) => {
+            if let Some((id, token)) = webhook {
+                delete_webhook(ctx, id, &token).await;
+            }
+            println!("[ACTION] Turned off auto-translation for channel (ID: {}).", channel_id);
+            "Done. I won't translate messages in this channel anymore.".to_string()
+        }
+        Err(e) => {
+            eprintln!("[ERROR] Failed to remove auto-translate setup for channel (ID: {}): {:?}", channel_id, e);
+            "Sorry, I couldn't turn that off right now.".to_string()
+        }
+    }
+}
+
+async fn delete_webhook(ctx: &Context, id: u64, token: &str) {
+    if let Err(e) = ctx.http.delete_webhook_with_token(id, token).await {
+        eprintln!("[ERROR] Could not delete webhook (ID: {}): {:?}", id, e);
+    }
+}
+
+/// Translates `text` into all `languages` with a single call, leaving out the language it
+/// is already written in.
+async fn translate_all(provider: &dyn LlmProvider, caller: &Caller, prompts: &Prompts, text: &str, languages: &[String]) -> Result<Vec<TranslatedText>, LlmError> {
+    let prompt = prompts.render("auto_translate", &[
+        ("languages", &languages.join(", ")),
+        ("text", &safety::delimit_user_text(text)),
+    ]);
+    let answer = call_llm_structured(provider, caller, None, &prompt, schema()).await?;
+    let parsed = match serde_json::from_str::<Translations>(&answer) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("[WARN] Auto-translation wasn't the expected JSON ({}), skipping it.", e);
+            return Ok(Vec::new());
+        }
+    };
+
+    let source = parsed.source_language.unwrap_or_default();
+    Ok(parsed.translations.into_iter()
+        .filter(|t| !t.language.trim().eq_ignore_ascii_case(source.trim()))
+        .filter(|t| !t.text.trim().is_empty() && t.text.trim() != text.trim())
+        .collect())
+}
+
+/// Translates `msg` if its channel is auto-translated.
+pub async fn handle_message(ctx: &Context, msg: &Message) {
+    let Some(guild_id) = msg.guild_id else { return };
+    if msg.author.bot || !msg.content.chars().any(char::is_alphabetic) {
+        return;
+    }
+    let (db, cooldowns, llm, prompts, max_chunks, caller) = {
+        let data = ctx.data.read().await;
+        (
+            data.get::<DatabaseKey>().expect("Expected DatabaseKey in TypeMap.").clone(),
+            data.get::<CooldownsKey>().expect("Expected CooldownsKey in TypeMap.").clone(),
+            data.get::<LlmProviderKey>().expect("Expected LlmProviderKey in TypeMap.").clone(),
+            data.get::<PromptsKey>().expect("Expected PromptsKey in TypeMap.").clone(),
+            data.get::<BotConfigKey>().expect("Expected BotConfigKey in TypeMap.").max_reply_chunks,
+            // Admins turned translation on for the channel; members shouldn't pay for it.
+            Caller::for_guild(&data, msg.author.id, guild_id, "autotranslate"),
+        )
+    };
+    let Some(config) = get_config(&db, msg.channel_id).await else { return };
+    if config.languages.is_empty() {
+        return;
+    }
+
+    if let Err(wait) = cooldowns.check("autotranslate", msg.author.id.0, msg.channel_id.0, Some(guild_id.0)) {
+        println!("[COOLDOWN] Not auto-translating message (ID: {}) for another {:?}.", msg.id, wait);
+        return;
+    }
+
+    let translations = match translate_all(llm.as_ref(), &caller, &prompts, &msg.content, &config.languages).await {
+        Ok(translations) if translations.is_empty() => return,
+        Ok(translations) => translations,
+        Err(e) => {
+            eprintln!("[ERROR] Auto-translation of message (ID: {}) failed: {}", msg.id, e);
+            return;
+        }
+    };
+    let content = translations.iter()
+        .map(|t| format!("**{}:** {}", safety::single_line(&t.language, 50), t.text.trim()))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if let Some((id, token)) = &config.webhook {
+        if post_as_author(ctx, msg, *id, token, &content).await {
+            return;
+        }
+    }
+    reply::send_reply_to(ctx, msg, &content, max_chunks).await;
+}
+
+/// Posts the translation through the channel's webhook with the author's name and avatar.
+/// `false` if the webhook is gone or failed before posting anything, so the caller can
+/// reply normally instead.
+async fn post_as_author(ctx: &Context, msg: &Message, id: u64, token: &str, content: &str) -> bool {
+    let webhook = match ctx.http.get_webhook_with_token(id, token).await {
+        Ok(webhook) => webhook,
+        Err(e) => {
+            eprintln!("[ERROR] Could not fetch auto-translate webhook (ID: {}): {:?}", id, e);
+            return false;
+        }
+    };
+    let name = msg.member.as_ref().and_then(|m| m.nick.clone()).unwrap_or_else(|| msg.author.name.clone());
+    let username: String = format!("{} (translated)", name).chars().take(MAX_USERNAME_CHARS).collect();
+
+    for (i, chunk) in reply::split_message(content, DISCORD_MESSAGE_LIMIT).iter().enumerate() {
+        if let Err(e) = webhook.execute(&ctx.http, false, |w| {
+            w.content(chunk).username(&username).avatar_url(msg.author.face()).allowed_mentions(|mentions| mentions.empty_parse())
+        }).await {
+            eprintln!("[ERROR] Could not post auto-translation through webhook (ID: {}): {:?}", id, e);
+            // Only fall back to a reply if nothing was posted yet.
+            return i > 0;
+        }
+    }
+    true
+}
@@ -0,0 +1,147 @@
+use crate::llm::GenerationSettings;
+use crate::safety::OutputFilter;
+use crate::trigger::TriggerMatcher;
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+
+/// Invite links are always blocked unless `NUGGIES_BLOCKED_LINKS` says otherwise.
+const DEFAULT_BLOCKED_LINKS: &str = "discord.gg,discord.com/invite,discordapp.com/invite";
+
+/// AI commands with their default temperature: precise for translations and summaries,
+/// playful for the slots quips. `None` leaves the backend's default.
+const COMMAND_TEMPERATURES: &[(&str, Option<f32>)] = &[
+    ("message", None),
+    ("nuggies", None),
+    ("ask", None),
+    ("translate", Some(0.2)),
+    ("autotranslate", Some(0.2)),
+    ("funfact", Some(1.0)),
+    ("slots", Some(1.4)),
+    ("summarize", Some(0.3)),
+    ("ambient", Some(1.0)),
+];
+
+/// Tunables read from the environment at startup. Everything here has a sensible default,
+/// so only DISCORD_TOKEN, TENOR_API_KEY, DATABASE_URL and the chosen provider's key are required.
+pub struct BotConfig {
+    /// Which `LlmProvider` answers: "gemini", "openai" (any OpenAI-compatible server) or "mock".
+    pub llm_provider: String,
+    pub gemini_model: String,
+    /// Base URL of the OpenAI-compatible API, e.g. "http://localhost:11434/v1" for Ollama.
+    pub openai_base_url: String,
+    pub openai_model: String,
+    /// Model that turns server facts into embeddings, see `knowledge`.
+    pub embedding_model: String,
+    /// Model that is tried once when a request to the primary model fails, "" for none.
+    pub fallback_model: Option<String>,
+    /// Model and sampling settings per AI command (see `COMMAND_TEMPERATURES`), set with
+    /// `LLM_<COMMAND>_MODEL`, `_TEMPERATURE`, `_TOP_P` and `_MAX_TOKENS`.
+    pub generation: HashMap<&'static str, GenerationSettings>,
+    /// How many past turns (user + model) of a channel's conversation are replayed to the model.
+    pub history_window: i64,
+    /// How many messages up a reply chain are included when someone replies to Nuggies.
+    pub reply_chain_depth: usize,
+    /// Replies that would need more Discord messages than this are attached as a .md file instead.
+    pub max_reply_chunks: usize,
+    /// Attachments per message Nuggies will look at.
+    pub max_attachments: usize,
+    /// Largest single attachment that is downloaded and sent to the model.
+    pub max_attachment_bytes: u64,
+    /// Whether PDFs and text files are accepted in addition to images.
+    pub attachment_documents: bool,
+    /// Overall timeout for a single request to the AI backend or Tenor.
+    pub upstream_timeout_secs: u64,
+    /// How often a failed request (network error, 429 or 5xx) is retried.
+    pub upstream_max_retries: u32,
+    /// Consecutive failed requests before an upstream's circuit breaker opens.
+    pub breaker_failure_threshold: u32,
+    /// How long an open circuit breaker rejects requests.
+    pub breaker_cooldown_secs: u64,
+    /// AI tokens (prompt + response) a single user may use per day. 0 means unlimited.
+    pub daily_user_token_quota: u64,
+    /// AI tokens all users of a guild together may use per day. 0 means unlimited.
+    pub daily_guild_token_quota: u64,
+    /// Applied to all AI output, built from `NUGGIES_BLOCKED_WORDS` and `NUGGIES_BLOCKED_LINKS`
+    /// (comma separated).
+    pub output_filter: OutputFilter,
+    /// Decides which messages talk to Nuggies; her name plus `NUGGIES_ALIASES` (comma separated).
+    pub trigger: TriggerMatcher,
+}
+
+impl BotConfig {
+    pub fn from_env() -> Self {
+        let llm_provider = env_or("LLM_PROVIDER", "gemini".to_string()).to_lowercase();
+        let default_fallback = if matches!(llm_provider.as_str(), "openai" | "mock") { "" } else { crate::llm::gemini::DEFAULT_GEMINI_FALLBACK_MODEL };
+        let default_embedding = if llm_provider == "openai" { "nomic-embed-text" } else { crate::llm::gemini::DEFAULT_GEMINI_EMBEDDING_MODEL };
+        BotConfig {
+            embedding_model: env_or("LLM_EMBEDDING_MODEL", default_embedding.to_string()),
+            fallback_model: Some(env_or("LLM_FALLBACK_MODEL", default_fallback.to_string())).filter(|model| !model.is_empty()),
+            generation: COMMAND_TEMPERATURES.iter().map(|(command, temperature)| (*command, generation_from_env(command, *temperature))).collect(),
+            llm_provider,
+            gemini_model: env_or("GEMINI_MODEL", crate::llm::gemini::DEFAULT_GEMINI_MODEL.to_string()),
+            openai_base_url: env_or("OPENAI_BASE_URL", "http://localhost:11434/v1".to_string()),
+            openai_model: env_or("OPENAI_MODEL", "llama3.1".to_string()),
+            history_window: env_or("NUGGIES_HISTORY_WINDOW", 20),
+            reply_chain_depth: env_or("NUGGIES_REPLY_CHAIN_DEPTH", 5),
+            max_reply_chunks: env_or("NUGGIES_MAX_REPLY_CHUNKS", 3),
+            max_attachments: env_or("NUGGIES_MAX_ATTACHMENTS", 4),
+            max_attachment_bytes: env_or("NUGGIES_MAX_ATTACHMENT_BYTES", 8 * 1024 * 1024),
+            attachment_documents: env_or("NUGGIES_ATTACHMENT_DOCUMENTS", true),
+            upstream_timeout_secs: env_or("UPSTREAM_TIMEOUT_SECS", 60),
+            upstream_max_retries: env_or("UPSTREAM_MAX_RETRIES", 3),
+            breaker_failure_threshold: env_or("BREAKER_FAILURE_THRESHOLD", 5),
+            breaker_cooldown_secs: env_or("BREAKER_COOLDOWN_SECS", 30),
+            daily_user_token_quota: env_or("NUGGIES_DAILY_USER_TOKENS", 200_000),
+            daily_guild_token_quota: env_or("NUGGIES_DAILY_GUILD_TOKENS", 2_000_000),
+            output_filter: OutputFilter::new(
+                &env_list("NUGGIES_BLOCKED_WORDS", ""),
+                &env_list("NUGGIES_BLOCKED_LINKS", DEFAULT_BLOCKED_LINKS),
+            ),
+            trigger: TriggerMatcher::new(&env_list("NUGGIES_ALIASES", "")),
+        }
+    }
+}
+
+impl BotConfig {
+    /// The generation settings for `command`, the backend's defaults for unknown commands.
+    pub fn generation_settings(&self, command: &str) -> GenerationSettings {
+        self.generation.get(command).cloned().unwrap_or_default()
+    }
+}
+
+fn generation_from_env(command: &str, default_temperature: Option<f32>) -> GenerationSettings {
+    let prefix = format!("LLM_{}", command.to_uppercase());
+    GenerationSettings {
+        model: env::var(format!("{}_MODEL", prefix)).ok().map(|model| model.trim().to_string()).filter(|model| !model.is_empty()),
+        temperature: env_opt(&format!("{}_TEMPERATURE", prefix), |t: &f32| (0.0..=2.0).contains(t)).or(default_temperature),
+        top_p: env_opt(&format!("{}_TOP_P", prefix), |p: &f32| (0.0..=1.0).contains(p)),
+        max_output_tokens: env_opt(&format!("{}_MAX_TOKENS", prefix), |n: &u32| *n > 0),
+    }
+}
+
+/// An optional setting, ignored with a warning if it doesn't parse or isn't `valid`.
+fn env_opt<T: FromStr>(name: &str, valid: impl Fn(&T) -> bool) -> Option<T> {
+    let value = env::var(name).ok()?;
+    match value.trim().parse().ok().filter(|parsed| valid(parsed)) {
+        Some(parsed) => Some(parsed),
+        None => {
+            eprintln!("[WARN] Invalid value '{}' for {}, ignoring it.", value, name);
+            None
+        }
+    }
+}
+
+fn env_list(name: &str, default: &str) -> Vec<String> {
+    env_or(name, default.to_string()).split(',').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect()
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            eprintln!("[WARN] Invalid value '{}' for {}, using the default instead.", value, name);
+            default
+        }),
+        Err(_) => default,
+    }
+}
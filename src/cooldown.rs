@@ -0,0 +1,240 @@
+//! Rate limits for the AI commands, so a single user, channel or guild can't spam Gemini.
+//! Every command has a token bucket per user, per channel and per guild; a use takes one
+//! token from each, and tokens trickle back in over time.
+//!
+//! Limits are written "uses/seconds" for user, channel and guild, e.g. "3/60,10/60,30/60"
+//! allows 3 uses per minute per user, 10 per channel and 30 per guild. They can be
+//! overridden per command with `COOLDOWN_<COMMAND>`, e.g. `COOLDOWN_ASK="3/60,10/60,30/60"`.
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets are only forgotten once there are this many, and only if they are full again.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Commands that are rate limited, with their default limits. "message" is the "nuggies"
+/// keyword trigger.
+const DEFAULT_LIMITS: &[(&str, &str)] = &[
+    ("message", "5/60,15/60,40/60"),
+    ("nuggies", "5/60,15/60,40/60"),
+    ("ask", "3/60,10/60,30/60"),
+    ("translate", "6/60,20/60,60/60"),
+    ("autotranslate", "10/60,20/60,60/60"),
+    ("funfact", "3/60,10/60,30/60"),
+    ("remember", "5/60,10/60,30/60"),
+    ("summarize", "2/300,3/300,10/300"),
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Limit {
+    capacity: f64,
+    refill_per_sec: f64,
+}
+
+impl Limit {
+    /// Parses "uses/seconds".
+    fn parse(text: &str) -> Option<Limit> {
+        let (uses, seconds) = text.trim().split_once('/')?;
+        let uses: f64 = uses.trim().parse().ok()?;
+        let seconds: f64 = seconds.trim().parse().ok()?;
+        if uses < 1.0 || seconds <= 0.0 {
+            return None;
+        }
+        Some(Limit { capacity: uses, refill_per_sec: uses / seconds })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CommandLimits {
+    user: Limit,
+    channel: Limit,
+    guild: Limit,
+}
+
+impl CommandLimits {
+    fn parse(text: &str) -> Option<CommandLimits> {
+        let mut limits = text.split(',').map(Limit::parse);
+        let parsed = CommandLimits { user: limits.next()??, channel: limits.next()??, guild: limits.next()?? };
+        if limits.next().is_some() { None } else { Some(parsed) }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Scope {
+    User,
+    Channel,
+    Guild,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: Limit, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.refill_per_sec).min(limit.capacity);
+        self.updated = now;
+    }
+
+    /// How long until the next token, zero if one is available.
+    fn wait(&self, limit: Limit) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / limit.refill_per_sec)
+        }
+    }
+}
+
+pub struct Cooldowns {
+    limits: HashMap<&'static str, CommandLimits>,
+    buckets: Mutex<HashMap<(&'static str, Scope, u64), Bucket>>,
+}
+
+impl Cooldowns {
+    pub fn from_env() -> Self {
+        let limits = DEFAULT_LIMITS.iter().map(|(command, default)| {
+            let default_limits = CommandLimits::parse(default).expect("default cooldowns are valid");
+            let name = format!("COOLDOWN_{}", command.to_uppercase());
+            let limits = match env::var(&name) {
+                Ok(value) => CommandLimits::parse(&value).unwrap_or_else(|| {
+                    eprintln!("[WARN] Invalid value '{}' for {}, using the default instead.", value, name);
+                    default_limits
+                }),
+                Err(_) => default_limits,
+            };
+            (*command, limits)
+        }).collect();
+        Cooldowns { limits, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Takes a use of `command` from the user's, channel's and guild's buckets. If any of
+    /// them is empty, nothing is taken and `Err` holds how long until all three allow it.
+    /// Commands without limits always pass.
+    pub fn check(&self, command: &str, user_id: u64, channel_id: u64, guild_id: Option<u64>) -> Result<(), Duration> {
+        self.check_at(command, user_id, channel_id, guild_id, Instant::now())
+    }
+
+    fn check_at(&self, command: &str, user_id: u64, channel_id: u64, guild_id: Option<u64>, now: Instant) -> Result<(), Duration> {
+        let (command, limits) = match self.limits.get_key_value(command) {
+            Some((command, limits)) => (*command, *limits),
+            None => return Ok(()),
+        };
+        let mut scopes = vec![(Scope::User, user_id, limits.user), (Scope::Channel, channel_id, limits.channel)];
+        if let Some(guild_id) = guild_id {
+            scopes.push((Scope::Guild, guild_id, limits.guild));
+        }
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if buckets.len() > PRUNE_THRESHOLD {
+            let all_limits = &self.limits;
+            buckets.retain(|(command, scope, _), bucket| {
+                let limits = all_limits[command];
+                let limit = match scope {
+                    Scope::User => limits.user,
+                    Scope::Channel => limits.channel,
+                    Scope::Guild => limits.guild,
+                };
+                bucket.refill(limit, now);
+                bucket.tokens < limit.capacity
+            });
+        }
+
+        let mut wait = Duration::ZERO;
+        for (scope, id, limit) in &scopes {
+            let bucket = buckets.entry((command, *scope, *id)).or_insert(Bucket { tokens: limit.capacity, updated: now });
+            bucket.refill(*limit, now);
+            wait = wait.max(bucket.wait(*limit));
+        }
+        if wait > Duration::ZERO {
+            return Err(wait);
+        }
+
+        for (scope, id, _) in &scopes {
+            if let Some(bucket) = buckets.get_mut(&(command, *scope, *id)) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// "Slow down! ..." message for a rate limited user.
+pub fn wait_message(wait: Duration) -> String {
+    let seconds = wait.as_secs_f64().ceil().max(1.0) as u64;
+    format!("Slow down! You can use that again in {} second{}.", seconds, if seconds == 1 { "" } else { "s" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cooldowns(limits: &str) -> Cooldowns {
+        Cooldowns {
+            limits: HashMap::from([("ask", CommandLimits::parse(limits).expect("valid limits"))]),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn limits_parse() {
+        let limits = CommandLimits::parse(" 3/60, 10/30 ,30/60").expect("valid limits");
+        assert_eq!((limits.user.capacity, limits.user.refill_per_sec), (3.0, 0.05));
+        assert_eq!((limits.channel.capacity, limits.channel.refill_per_sec), (10.0, 10.0 / 30.0));
+        assert_eq!(limits.guild.capacity, 30.0);
+        assert!(DEFAULT_LIMITS.iter().all(|(_, limits)| CommandLimits::parse(limits).is_some()));
+    }
+
+    #[test]
+    fn invalid_limits_are_rejected() {
+        for text in ["", "3/60", "3/60,10/60", "3/60,10/60,30/60,1/1", "3,10,30", "x/60,10/60,30/60", "0/60,10/60,30/60", "3/0,10/60,30/60", "3/-1,10/60,30/60"] {
+            assert!(CommandLimits::parse(text).is_none(), "{:?} should not parse", text);
+        }
+    }
+
+    #[test]
+    fn an_empty_bucket_says_how_long_to_wait() {
+        let cooldowns = cooldowns("2/10,10/10,10/10");
+        let now = Instant::now();
+        assert!(cooldowns.check_at("ask", 1, 1, Some(1), now).is_ok());
+        assert!(cooldowns.check_at("ask", 1, 1, Some(1), now).is_ok());
+        assert_eq!(cooldowns.check_at("ask", 1, 1, Some(1), now), Err(Duration::from_secs(5)));
+        // Other users have their own bucket; commands without limits always pass.
+        assert!(cooldowns.check_at("ask", 2, 1, Some(1), now).is_ok());
+        assert!(cooldowns.check_at("fox", 1, 1, Some(1), now).is_ok());
+    }
+
+    #[test]
+    fn a_refused_use_takes_nothing_from_the_other_buckets() {
+        let cooldowns = cooldowns("10/10,1/10,10/10");
+        let now = Instant::now();
+        assert!(cooldowns.check_at("ask", 1, 1, Some(1), now).is_ok());
+        assert!(cooldowns.check_at("ask", 1, 1, Some(1), now).is_err());
+        // The channel bucket refused, so the user still has 9 uses left elsewhere.
+        for channel in 2..=10 {
+            assert!(cooldowns.check_at("ask", 1, channel, Some(1), now).is_ok());
+        }
+        assert!(cooldowns.check_at("ask", 1, 11, Some(1), now).is_err());
+    }
+
+    #[test]
+    fn tokens_trickle_back_in() {
+        let cooldowns = cooldowns("2/10,10/10,10/10");
+        let start = Instant::now();
+        assert!(cooldowns.check_at("ask", 1, 1, None, start).is_ok());
+        assert!(cooldowns.check_at("ask", 1, 1, None, start).is_ok());
+
+        let later = start + Duration::from_secs(3);
+        assert_eq!(cooldowns.check_at("ask", 1, 1, None, later), Err(Duration::from_secs(2)));
+        assert!(cooldowns.check_at("ask", 1, 1, None, start + Duration::from_secs(5)).is_ok());
+        // Never more than the capacity, however long it's been.
+        let much_later = start + Duration::from_secs(3600);
+        assert!(cooldowns.check_at("ask", 1, 1, None, much_later).is_ok());
+        assert!(cooldowns.check_at("ask", 1, 1, None, much_later).is_ok());
+        assert!(cooldowns.check_at("ask", 1, 1, None, much_later).is_err());
+    }
+}
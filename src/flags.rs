@@ -0,0 +1,192 @@
+//! Translating a message when someone reacts to it with a country flag, e.g. 🇩🇪 for German.
+//! Servers opt in with `/translation flags`. Every message is translated at most once per
+//! language, however many people react with the flag.
+
+use crate::usage::Caller;
+use crate::{reply, translate, BotConfigKey, CooldownsKey, Database, DatabaseKey, LlmProviderKey, PromptsKey};
+use bb8::RunError;
+use serenity::client::Context;
+use serenity::model::channel::{Reaction, ReactionType};
+use serenity::model::id::{GuildId, MessageId};
+use tokio_postgres::types::ToSql;
+
+/// Countries and the language a flag of theirs asks for. Countries with several common
+/// languages get the one most people there would expect.
+const COUNTRY_LANGUAGES: &[(&str, &str)] = &[
+    ("GB", "English"), ("US", "English"), ("AU", "English"), ("CA", "English"), ("NZ", "English"), ("IE", "English"),
+    ("DE", "German"), ("AT", "German"), ("CH", "German"), ("LI", "German"),
+    ("FR", "French"), ("BE", "French"), ("LU", "French"),
+    ("ES", "Spanish"), ("MX", "Spanish"), ("AR", "Spanish"), ("CO", "Spanish"), ("CL", "Spanish"), ("PE", "Spanish"), ("VE", "Spanish"), ("CU", "Spanish"),
+    ("IT", "Italian"), ("PT", "Portuguese"), ("BR", "Portuguese"), ("NL", "Dutch"),
+    ("PL", "Polish"), ("RU", "Russian"), ("UA", "Ukrainian"), ("BY", "Belarusian"),
+    ("JP", "Japanese"), ("KR", "Korean"), ("CN", "Chinese (Simplified)"), ("TW", "Chinese (Traditional)"), ("HK", "Chinese (Traditional)"),
+    ("TR", "Turkish"), ("SE", "Swedish"), ("NO", "Norwegian"), ("DK", "Danish"), ("FI", "Finnish"), ("IS", "Icelandic"),
+    ("CZ", "Czech"), ("SK", "Slovak"), ("HU", "Hungarian"), ("RO", "Romanian"), ("MD", "Romanian"), ("BG", "Bulgarian"),
+    ("HR", "Croatian"), ("RS", "Serbian"), ("SI", "Slovenian"), ("BA", "Bosnian"), ("MK", "Macedonian"), ("AL", "Albanian"),
+    ("GR", "Greek"), ("CY", "Greek"), ("LT", "Lithuanian"), ("LV", "Latvian"), ("EE", "Estonian"),
+    ("IL", "Hebrew"), ("SA", "Arabic"), ("EG", "Arabic"), ("AE", "Arabic"), ("MA", "Arabic"), ("IR", "Persian"),
+    ("IN", "Hindi"), ("PK", "Urdu"), ("BD", "Bengali"), ("TH", "Thai"), ("VN", "Vietnamese"),
+    ("ID", "Indonesian"), ("MY", "Malay"), ("PH", "Tagalog"), ("KE", "Swahili"), ("TZ", "Swahili"),
+    ("GE", "Georgian"), ("AM", "Armenian"), ("VA", "Latin"),
+];
+
+/// The language of a flag emoji (two regional indicator symbols), if it is a known country.
+pub fn language_for_flag(emoji: &str) -> Option<&'static str> {
+    let letters: Vec<char> = emoji.chars()
+        .map(|c| match c as u32 {
+            code @ 0x1F1E6..=0x1F1FF => char::from_u32(code - 0x1F1E6 + 'A' as u32),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    if letters.len() != 2 {
+        return None;
+    }
+    let country: String = letters.into_iter().collect();
+    COUNTRY_LANGUAGES.iter().find(|(code, _)| *code == country).map(|(_, language)| *language)
+}
+
+/// Whether the guild has flag translations turned on. Off by default and when the database
+/// can't be reached.
+pub async fn is_enabled(db: &Database, guild_id: GuildId) -> bool {
+    let conn = match db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection for flag translation lookup: {:?}", e);
+            return false;
+        }
+    };
+    let guild_id_i64 = guild_id.0 as i64;
+    match conn.query_opt("SELECT enabled FROM guild_flag_translation WHERE guild_id = $1", &[&guild_id_i64]).await {
+        Ok(row) => row.is_some_and(|r| r.get(0)),
+        Err(e) => {
+            eprintln!("[ERROR] Failed to load flag translation setting for Guild (ID: {}): {:?}", guild_id, e);
+            false
+        }
+    }
+}
+
+async fn set_enabled(db: &Database, guild_id: GuildId, enabled: bool, updated_by: u64) -> Result<u64, RunError<tokio_postgres::Error>> {
+    let conn = db.pool.get().await?;
+    let guild_id_i64 = guild_id.0 as i64;
+    let updated_by_i64 = updated_by as i64;
+    let params: &[&(dyn ToSql + Sync)] = &[&guild_id_i64, &enabled, &updated_by_i64];
+    Ok(conn.execute(
+        "INSERT INTO guild_flag_translation (guild_id, enabled, updated_by, updated_at) VALUES ($1, $2, $3, NOW())
+         ON CONFLICT (guild_id) DO UPDATE SET enabled = EXCLUDED.enabled, updated_by = EXCLUDED.updated_by, updated_at = NOW()",
+        params,
+    ).await?)
+}
+
+/// Turns flag translations on or off, returning the reply for the admin.
+pub async fn save_setting(db: &Database, guild_id: GuildId, enabled: bool, updated_by: u64) -> String {
+    match set_enabled(db, guild_id, enabled, updated_by).await {
+        Ok(_) => {
+            println!("[ACTION] User (ID: {}) turned flag translations {} for Guild (ID: {}).", updated_by, if enabled { "on" } else { "off" }, guild_id);
+            if enabled {
+                "Done. React to a message with a country flag and I'll translate it into that country's language.".to_string()
+            } else {
+                "Done. Flag reactions won't translate messages anymore.".to_string()
+            }
+        }
+        Err(e) => {
+            eprintln!("[ERROR] Failed to save flag translation setting for Guild (ID: {}): {:?}", guild_id, e);
+            "Sorry, I couldn't save that right now.".to_string()
+        }
+    }
+}
+
+/// Records that the message is being translated into `language`. `false` if it already was
+/// (or is right now), so the translation isn't posted twice.
+async fn claim(db: &Database, message_id: MessageId, language: &str) -> bool {
+    let conn = match db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection for flag translation: {:?}", e);
+            return false;
+        }
+    };
+    let message_id_i64 = message_id.0 as i64;
+    let params: &[&(dyn ToSql + Sync)] = &[&message_id_i64, &language];
+    match conn.execute(
+        "INSERT INTO flag_translations (message_id, language) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        params,
+    ).await {
+        Ok(inserted) => inserted == 1,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to record flag translation for message (ID: {}): {:?}", message_id, e);
+            false
+        }
+    }
+}
+
+/// Forgets a claim whose translation failed, so another reaction can try again.
+async fn release(db: &Database, message_id: MessageId, language: &str) {
+    let Ok(conn) = db.pool.get().await else { return };
+    let message_id_i64 = message_id.0 as i64;
+    let params: &[&(dyn ToSql + Sync)] = &[&message_id_i64, &language];
+    if let Err(e) = conn.execute("DELETE FROM flag_translations WHERE message_id = $1 AND language = $2", params).await {
+        eprintln!("[ERROR] Failed to release flag translation for message (ID: {}): {:?}", message_id, e);
+    }
+}
+
+/// Translates the reacted-to message if the reaction is a country flag and the guild has
+/// flag translations turned on. The translation is posted as a reply to the message.
+pub async fn handle_reaction(ctx: &Context, reaction: &Reaction) {
+    let (Some(guild_id), Some(user_id)) = (reaction.guild_id, reaction.user_id) else { return };
+    let language = match &reaction.emoji {
+        ReactionType::Unicode(emoji) => match language_for_flag(emoji) {
+            Some(language) => language,
+            None => return,
+        },
+        _ => return,
+    };
+    if reaction.user(&ctx.http).await.map_or(true, |u| u.bot) {
+        return;
+    }
+
+    let (db, cooldowns, llm, prompts, max_chunks, caller) = {
+        let data = ctx.data.read().await;
+        (
+            data.get::<DatabaseKey>().expect("Expected DatabaseKey in TypeMap.").clone(),
+            data.get::<CooldownsKey>().expect("Expected CooldownsKey in TypeMap.").clone(),
+            data.get::<LlmProviderKey>().expect("Expected LlmProviderKey in TypeMap.").clone(),
+            data.get::<PromptsKey>().expect("Expected PromptsKey in TypeMap.").clone(),
+            data.get::<BotConfigKey>().expect("Expected BotConfigKey in TypeMap.").max_reply_chunks,
+            Caller::new(&data, user_id, Some(guild_id), "translate"),
+        )
+    };
+    if !is_enabled(&db, guild_id).await {
+        return;
+    }
+    if !claim(&db, reaction.message_id, language).await {
+        return;
+    }
+    if let Err(wait) = cooldowns.check("translate", user_id.0, reaction.channel_id.0, Some(guild_id.0)) {
+        println!("[COOLDOWN] User (ID: {}) is rate limited on flag translations for another {:?}.", user_id, wait);
+        release(&db, reaction.message_id, language).await;
+        return;
+    }
+
+    let message = match reaction.message(&ctx.http).await {
+        Ok(message) if !message.content.trim().is_empty() => message,
+        Ok(_) => {
+            // Nothing to translate yet; the message may still be edited to have text.
+            release(&db, reaction.message_id, language).await;
+            return;
+        }
+        Err(e) => {
+            eprintln!("[ERROR] Could not fetch message (ID: {}) to translate: {:?}", reaction.message_id, e);
+            release(&db, reaction.message_id, language).await;
+            return;
+        }
+    };
+    println!("[ACTION] User (ID: {}) asked for message (ID: {}) in {} with a flag.", user_id, message.id, language);
+
+    match translate::translate(llm.as_ref(), &caller, &prompts, &message.content, language).await {
+        Ok(translation) => reply::send_reply_to(ctx, &message, &translate::render(&translation, language), max_chunks).await,
+        Err(e) => {
+            eprintln!("[ERROR] Flag translation of message (ID: {}) failed: {}", message.id, e);
+            release(&db, reaction.message_id, language).await;
+        }
+    }
+}
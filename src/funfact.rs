@@ -0,0 +1,151 @@
+//! `/funfact`: a fact asked for as structured JSON (topic, fact, source, confidence) and
+//! shown as an embed, with an "Another one" button that asks again about the same topic.
+
+use crate::cooldown;
+use crate::llm::{LlmError, LlmProvider};
+use crate::prompts::Prompts;
+use crate::safety;
+use crate::usage::Caller;
+use crate::{call_llm_structured, llm_error_reply, persona, CooldownsKey, DatabaseKey, LlmProviderKey, PromptsKey};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use serenity::builder::{CreateComponents, CreateEmbed, EditInteractionResponse};
+use serenity::client::Context;
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::application::interaction::InteractionResponseType;
+
+/// Custom ID prefix of the "Another one" button; the topic follows it.
+pub const BUTTON_PREFIX: &str = "funfact_again:";
+/// Custom IDs are limited to 100 characters, so this is as long as a topic can get.
+const MAX_TOPIC_CHARS: usize = 80;
+const EMBED_COLOUR: u32 = 0xE67E22;
+const MAX_FACT_CHARS: usize = 4000;
+
+#[derive(Deserialize, Debug)]
+pub struct FunFact {
+    pub topic: String,
+    pub fact: String,
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub confidence: Option<String>,
+}
+
+fn schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "topic": { "type": "string", "description": "The topic of the fact, in a few words." },
+            "fact": { "type": "string", "description": "The fun fact itself, one to three sentences." },
+            "source": { "type": "string", "description": "Where the fact can be checked, e.g. a book or website. Leave empty if unsure." },
+            "confidence": { "type": "string", "enum": ["low", "medium", "high"], "description": "How sure you are that the fact is accurate." }
+        },
+        "required": ["topic", "fact", "confidence"]
+    })
+}
+
+/// `topic` as it is kept in the button's custom ID.
+pub fn normalize_topic(topic: &str) -> String {
+    safety::single_line(topic, MAX_TOPIC_CHARS)
+}
+
+fn prompt(prompts: &Prompts, topic: &str) -> String {
+    if topic.eq_ignore_ascii_case("random") {
+        prompts.render("funfact_random", &[])
+    } else {
+        prompts.render("funfact_topic", &[("topic", &safety::delimit_user_text(topic))])
+    }
+}
+
+/// Asks for a fact about `topic` ("random" for any). A backend that ignores the schema and
+/// answers with plain text still gets its answer shown, as the fact.
+pub async fn generate(provider: &dyn LlmProvider, caller: &Caller, prompts: &Prompts, persona: &str, topic: &str) -> Result<FunFact, LlmError> {
+    let text = call_llm_structured(provider, caller, Some(persona), &prompt(prompts, topic), schema()).await?;
+    Ok(serde_json::from_str::<FunFact>(&text).unwrap_or_else(|e| {
+        eprintln!("[WARN] Fun fact wasn't the expected JSON ({}), showing it as plain text.", e);
+        FunFact { topic: topic.to_string(), fact: text, source: None, confidence: None }
+    }))
+}
+
+fn embed(fact: &FunFact) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+    embed.title(format!("🦊 Fun fact: {}", safety::single_line(&fact.topic, 200)))
+        .description(fact.fact.chars().take(MAX_FACT_CHARS).collect::<String>())
+        .colour(EMBED_COLOUR);
+    if let Some(source) = fact.source.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+        embed.field("Source", safety::single_line(source, 1000), false);
+    }
+    if let Some(confidence) = &fact.confidence {
+        let marker = match confidence.to_lowercase().as_str() {
+            "high" => "🟢",
+            "medium" => "🟡",
+            _ => "🔴",
+        };
+        embed.footer(|footer| footer.text(format!("Confidence: {} {}", marker, confidence.to_lowercase())));
+    }
+    embed
+}
+
+fn button<'a>(components: &'a mut CreateComponents, topic: &str) -> &'a mut CreateComponents {
+    components.create_action_row(|row| {
+        row.create_button(|button| {
+            button.style(ButtonStyle::Primary)
+                .label("Another one")
+                .custom_id(format!("{}{}", BUTTON_PREFIX, normalize_topic(topic)))
+        })
+    })
+}
+
+/// Fills a (deferred) interaction response with the fact's embed and the button.
+pub fn fill_response<'a>(response: &'a mut EditInteractionResponse, fact: &FunFact, topic: &str) -> &'a mut EditInteractionResponse {
+    response.content("")
+        .set_embed(embed(fact))
+        .components(|components| button(components, topic))
+        .allowed_mentions(|mentions| mentions.empty_parse())
+}
+
+/// "Another one": posts a new fact about the topic stored in the button.
+pub async fn handle_button(ctx: &Context, component: &MessageComponentInteraction) {
+    let topic = component.data.custom_id.trim_start_matches(BUTTON_PREFIX).to_string();
+    println!("[BUTTON] User '{}' (ID: {}) asked for another fun fact about '{}'.", component.user.name, component.user.id, topic);
+
+    let cooldowns = ctx.data.read().await.get::<CooldownsKey>().expect("Expected CooldownsKey in TypeMap.").clone();
+    if let Err(wait) = cooldowns.check("funfact", component.user.id.0, component.channel_id.0, component.guild_id.map(|id| id.0)) {
+        if let Err(e) = component.create_interaction_response(&ctx.http, |response| {
+            response.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|data| data.content(cooldown::wait_message(wait)).ephemeral(true))
+        }).await {
+            eprintln!("[ERROR] Could not send cooldown response: {:?}", e);
+        }
+        return;
+    }
+
+    if let Err(e) = component.create_interaction_response(&ctx.http, |response| {
+        response.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+    }).await {
+        eprintln!("[ERROR] Could not defer fun fact button: {:?}", e);
+        return;
+    }
+
+    let (llm, db, prompts, caller) = {
+        let data = ctx.data.read().await;
+        (
+            data.get::<LlmProviderKey>().expect("Expected LlmProviderKey in TypeMap.").clone(),
+            data.get::<DatabaseKey>().expect("Expected DatabaseKey in TypeMap.").clone(),
+            data.get::<PromptsKey>().expect("Expected PromptsKey in TypeMap.").clone(),
+            Caller::new(&data, component.user.id, component.guild_id, "funfact"),
+        )
+    };
+    let personality_prompt = persona::get_personality(&db, component.guild_id).await;
+
+    let result = match generate(llm.as_ref(), &caller, &prompts, &personality_prompt, &topic).await {
+        Ok(fact) => component.edit_original_interaction_response(&ctx.http, |response| fill_response(response, &fact, &topic)).await,
+        Err(e) => component.edit_original_interaction_response(&ctx.http, |response| {
+            response.content(llm_error_reply("funfact", &e))
+        }).await,
+    };
+    if let Err(e) = result {
+        eprintln!("[ERROR] Could not edit fun fact button response: {:?}", e);
+    }
+}
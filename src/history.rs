@@ -0,0 +1,83 @@
+use crate::Database;
+use bb8::RunError;
+use tokio_postgres::types::ToSql;
+
+/// A single remembered turn of a Nuggies conversation.
+/// `role` is either "user" or "model".
+pub struct HistoryTurn {
+    pub role: String,
+    pub author_name: Option<String>,
+    pub content: String,
+}
+
+impl HistoryTurn {
+    /// The text sent to the model for this turn. User turns are prefixed with the
+    /// author's name so Nuggies can tell people apart in a shared channel.
+    pub fn as_prompt_text(&self) -> String {
+        match (&self.role[..], &self.author_name) {
+            ("user", Some(name)) => format!("{}: {}", name, self.content),
+            _ => self.content.clone(),
+        }
+    }
+}
+
+/// Loads the most recent `window` turns for a channel (threads have their own channel ID,
+/// so they get their own history), oldest first.
+pub async fn load_history(db: &Database, channel_id: u64, window: i64) -> Vec<HistoryTurn> {
+    let conn = match db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection for conversation history: {:?}", e);
+            return Vec::new();
+        }
+    };
+
+    let channel_id_i64 = channel_id as i64;
+    let params: &[&(dyn ToSql + Sync)] = &[&channel_id_i64, &window];
+    match conn.query(
+        "SELECT role, author_name, content FROM (
+            SELECT id, role, author_name, content FROM conversation_history
+            WHERE channel_id = $1 ORDER BY id DESC LIMIT $2
+        ) recent ORDER BY id ASC",
+        params,
+    ).await {
+        Ok(rows) => rows.iter().map(|row| HistoryTurn {
+            role: row.get(0),
+            author_name: row.get(1),
+            content: row.get(2),
+        }).collect(),
+        Err(e) => {
+            eprintln!("[ERROR] Failed to load conversation history for channel (ID: {}): {:?}", channel_id, e);
+            Vec::new()
+        }
+    }
+}
+
+/// Stores a user message together with Nuggies' reply so both are replayed next time.
+pub async fn append_exchange(db: &Database, channel_id: u64, user_id: u64, author_name: &str, user_text: &str, model_text: &str) {
+    let conn = match db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection for conversation history: {:?}", e);
+            return;
+        }
+    };
+
+    let channel_id_i64 = channel_id as i64;
+    let user_id_i64 = user_id as i64;
+    let params: &[&(dyn ToSql + Sync)] = &[&channel_id_i64, &user_id_i64, &author_name, &user_text, &model_text];
+    if let Err(e) = conn.execute(
+        "INSERT INTO conversation_history (channel_id, role, user_id, author_name, content)
+         VALUES ($1, 'user', $2, $3, $4), ($1, 'model', NULL, NULL, $5)",
+        params,
+    ).await {
+        eprintln!("[ERROR] Failed to store conversation history for channel (ID: {}): {:?}", channel_id, e);
+    }
+}
+
+/// Wipes the conversation history of a channel. Returns the number of deleted turns.
+pub async fn clear_history(db: &Database, channel_id: u64) -> Result<u64, RunError<tokio_postgres::Error>> {
+    let conn = db.pool.get().await?;
+    let channel_id_i64 = channel_id as i64;
+    Ok(conn.execute("DELETE FROM conversation_history WHERE channel_id = $1", &[&channel_id_i64]).await?)
+}
@@ -0,0 +1,236 @@
+//! Server facts that members teach Nuggies with `/remember`, so she knows a server's in-jokes,
+//! event times and rules. Every fact is stored with an embedding; when Nuggies answers in
+//! the server, the facts closest to the message are added to the prompt.
+
+use crate::llm::{EmbeddingTask, LlmError, LlmProvider, TokenUsage};
+use crate::safety;
+use crate::usage::{self, Caller};
+use crate::Database;
+use bb8::RunError;
+use serenity::model::id::{GuildId, UserId};
+use std::cmp::Ordering;
+use tokio_postgres::types::ToSql;
+
+pub const MAX_FACT_LENGTH: usize = 300;
+const MAX_FACTS_PER_GUILD: i64 = 200;
+const FACTS_PER_PAGE: i64 = 10;
+/// At most this many facts are added to a prompt.
+const MAX_RELEVANT_FACTS: usize = 3;
+/// Facts less similar to the message than this are left out, however few there are.
+const MIN_SIMILARITY: f32 = 0.5;
+
+/// Embeds and saves a fact taught by `caller`, returning the reply for the user.
+pub async fn remember(provider: &dyn LlmProvider, caller: &Caller, guild_id: GuildId, fact: &str) -> String {
+    let fact = fact.trim();
+    if fact.is_empty() {
+        return "Tell me what to remember, e.g. `Movie night is every Friday at 8pm CET`.".to_string();
+    }
+    if fact.chars().count() > MAX_FACT_LENGTH {
+        return format!("That's a lot to remember. Keep facts under {} characters.", MAX_FACT_LENGTH);
+    }
+    let fact = safety::single_line(fact, MAX_FACT_LENGTH);
+
+    let guild_id_i64 = guild_id.0 as i64;
+    match count_facts(&caller.db, guild_id).await {
+        Ok(count) if count >= MAX_FACTS_PER_GUILD => {
+            return format!("I already know {} facts about this server. Use `/forget` to make room first.", MAX_FACTS_PER_GUILD);
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("[ERROR] Failed to count facts for Guild (ID: {}): {:?}", guild_id, e);
+            return "Sorry, I couldn't save that right now.".to_string();
+        }
+    }
+
+    // The embedding is a network call with retries; no pooled connection is held during it.
+    let embedding = match embed(provider, caller, &fact, EmbeddingTask::Document).await {
+        Ok(embedding) => embedding,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to embed fact for Guild (ID: {}): {}", guild_id, e);
+            return format!("I couldn't learn that right now. {}", e.user_reason());
+        }
+    };
+    let conn = match caller.db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection to save a fact: {:?}", e);
+            return "Sorry, I couldn't save that right now.".to_string();
+        }
+    };
+    let user_id_i64 = caller.user_id.0 as i64;
+    let params: &[&(dyn ToSql + Sync)] = &[&guild_id_i64, &fact, &embedding, &user_id_i64];
+    match conn.query_one(
+        "INSERT INTO guild_facts (guild_id, fact, embedding, created_by) VALUES ($1, $2, $3, $4) RETURNING id",
+        params,
+    ).await {
+        Ok(row) => {
+            let id: i64 = row.get(0);
+            println!("[ACTION] User (ID: {}) taught fact #{} to Guild (ID: {}).", caller.user_id, id, guild_id);
+            format!("Got it, I'll remember that (fact #{}).", id)
+        }
+        Err(e) => {
+            eprintln!("[ERROR] Failed to save fact for Guild (ID: {}): {:?}", guild_id, e);
+            "Sorry, I couldn't save that right now.".to_string()
+        }
+    }
+}
+
+async fn count_facts(db: &Database, guild_id: GuildId) -> Result<i64, RunError<tokio_postgres::Error>> {
+    let conn = db.pool.get().await?;
+    let guild_id_i64 = guild_id.0 as i64;
+    Ok(conn.query_one("SELECT COUNT(*) FROM guild_facts WHERE guild_id = $1", &[&guild_id_i64]).await?.get(0))
+}
+
+/// Deletes a fact, returning the reply for the user. Members can only delete their own
+/// facts, server managers any of them.
+pub async fn forget(db: &Database, guild_id: GuildId, id: i64, user_id: UserId, can_manage: bool) -> String {
+    let conn = match db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection to delete a fact: {:?}", e);
+            return "Sorry, I couldn't do that right now.".to_string();
+        }
+    };
+    let guild_id_i64 = guild_id.0 as i64;
+    let created_by: i64 = match conn.query_opt("SELECT created_by FROM guild_facts WHERE id = $1 AND guild_id = $2", &[&id, &guild_id_i64]).await {
+        Ok(Some(row)) => row.get(0),
+        Ok(None) => return format!("I don't know a fact #{} on this server. `/facts` lists them all.", id),
+        Err(e) => {
+            eprintln!("[ERROR] Failed to load fact #{} for Guild (ID: {}): {:?}", id, guild_id, e);
+            return "Sorry, I couldn't do that right now.".to_string();
+        }
+    };
+    if created_by as u64 != user_id.0 && !can_manage {
+        return "You can only make me forget facts you taught me. Ask someone with the Manage Server permission.".to_string();
+    }
+
+    match conn.execute("DELETE FROM guild_facts WHERE id = $1", &[&id]).await {
+        Ok(_) => {
+            println!("[ACTION] User (ID: {}) deleted fact #{} from Guild (ID: {}).", user_id, id, guild_id);
+            format!("Done. I've forgotten fact #{}.", id)
+        }
+        Err(e) => {
+            eprintln!("[ERROR] Failed to delete fact #{} for Guild (ID: {}): {:?}", id, guild_id, e);
+            "Sorry, I couldn't do that right now.".to_string()
+        }
+    }
+}
+
+/// One page of the guild's facts, oldest first.
+pub async fn list(db: &Database, guild_id: GuildId, page: i64) -> String {
+    let conn = match db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection to list facts: {:?}", e);
+            return "Sorry, I couldn't look that up right now.".to_string();
+        }
+    };
+    let guild_id_i64 = guild_id.0 as i64;
+    let total: i64 = match conn.query_one("SELECT COUNT(*) FROM guild_facts WHERE guild_id = $1", &[&guild_id_i64]).await {
+        Ok(row) => row.get(0),
+        Err(e) => {
+            eprintln!("[ERROR] Failed to count facts for Guild (ID: {}): {:?}", guild_id, e);
+            return "Sorry, I couldn't look that up right now.".to_string();
+        }
+    };
+    if total == 0 {
+        return "I don't know anything about this server yet. Teach me with `/remember`.".to_string();
+    }
+
+    let pages = (total + FACTS_PER_PAGE - 1) / FACTS_PER_PAGE;
+    let page = page.clamp(1, pages);
+    let offset = (page - 1) * FACTS_PER_PAGE;
+    let params: &[&(dyn ToSql + Sync)] = &[&guild_id_i64, &FACTS_PER_PAGE, &offset];
+    match conn.query("SELECT id, fact, created_by FROM guild_facts WHERE guild_id = $1 ORDER BY id LIMIT $2 OFFSET $3", params).await {
+        Ok(rows) => {
+            let lines: Vec<String> = rows.iter().map(|row| {
+                let id: i64 = row.get(0);
+                let fact: String = row.get(1);
+                let created_by: i64 = row.get(2);
+                format!("**#{}** {} (by <@{}>)", id, fact, created_by)
+            }).collect();
+            format!("**What I know about this server** (page {}/{}, {} facts)\n{}", page, pages, total, lines.join("\n"))
+        }
+        Err(e) => {
+            eprintln!("[ERROR] Failed to load facts for Guild (ID: {}): {:?}", guild_id, e);
+            "Sorry, I couldn't look that up right now.".to_string()
+        }
+    }
+}
+
+/// The guild's facts most related to `query`, best first. Empty outside of guilds, when the
+/// guild has no facts (without calling the embedding model), or when anything fails. The
+/// embedding counts towards `caller`'s usage.
+pub async fn relevant_facts(provider: &dyn LlmProvider, caller: &Caller, query: &str) -> Vec<String> {
+    let Some(guild_id) = caller.guild_id else { return Vec::new() };
+    let facts = match load_facts(&caller.db, guild_id).await {
+        Ok(facts) => facts,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to load facts for Guild (ID: {}): {:?}", guild_id, e);
+            return Vec::new();
+        }
+    };
+    if facts.is_empty() {
+        return Vec::new();
+    }
+
+    let query_embedding = match embed(provider, caller, query, EmbeddingTask::Query).await {
+        Ok(embedding) => embedding,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to embed message for fact lookup: {}", e);
+            return Vec::new();
+        }
+    };
+    let mut scored: Vec<(f32, String)> = facts.into_iter()
+        .filter_map(|(fact, embedding)| cosine_similarity(&query_embedding, &embedding).map(|score| (score, fact)))
+        .filter(|(score, _)| *score >= MIN_SIMILARITY)
+        .collect();
+    scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+    scored.into_iter().take(MAX_RELEVANT_FACTS).map(|(_, fact)| fact).collect()
+}
+
+/// Every fact of the guild with its embedding.
+async fn load_facts(db: &Database, guild_id: GuildId) -> Result<Vec<(String, Vec<f32>)>, RunError<tokio_postgres::Error>> {
+    let conn = db.pool.get().await?;
+    let guild_id_i64 = guild_id.0 as i64;
+    let rows = conn.query("SELECT fact, embedding FROM guild_facts WHERE guild_id = $1", &[&guild_id_i64]).await?;
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+}
+
+/// Embeds `text` for `caller`, within their quota. Embedding APIs don't all report token
+/// counts (Gemini's doesn't), so about four characters per token are recorded.
+async fn embed(provider: &dyn LlmProvider, caller: &Caller, text: &str, task: EmbeddingTask) -> Result<Vec<f32>, LlmError> {
+    usage::check_quota(caller).await?;
+    let embedding = provider.embed(text, task).await?;
+    let prompt_tokens = (text.chars().count() as u32).div_ceil(4);
+    usage::record(caller, TokenUsage { prompt_tokens, response_tokens: 0 }).await;
+    Ok(embedding)
+}
+
+/// The facts as a prompt section, "" if there are none. Members wrote them, so they are
+/// delimited like any other user text.
+pub fn render(facts: &[String]) -> String {
+    if facts.is_empty() {
+        return String::new();
+    }
+    let list = facts.iter().map(|fact| format!("- {}", fact)).collect::<Vec<_>>().join("\n");
+    format!(
+        "Members of this server taught you these facts. Use them if they help with the message:\n{}\n\n",
+        safety::delimit_user_text(&list)
+    )
+}
+
+/// `None` for vectors of different lengths, e.g. facts embedded before the embedding model
+/// was changed.
+fn cosine_similarity(a: &[f32], b: &[f32]) -> Option<f32> {
+    if a.len() != b.len() || a.is_empty() {
+        return None;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return None;
+    }
+    Some(dot / (norm_a * norm_b))
+}
@@ -0,0 +1,152 @@
+//! Wraps a provider so requests that fail on the primary model are retried once on a
+//! fallback model of the same backend, e.g. when Gemini is overloaded or has retired a model.
+
+use super::{EmbeddingTask, LlmError, LlmProvider, LlmRequest, LlmResponse};
+use serenity::async_trait;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub struct FallbackProvider {
+    inner: Arc<dyn LlmProvider>,
+    model: String,
+}
+
+impl FallbackProvider {
+    pub fn new(inner: Arc<dyn LlmProvider>, model: String) -> Self {
+        FallbackProvider { inner, model }
+    }
+
+    /// The request for the fallback model, or `None` if it already asked for that model.
+    fn fallback_request(&self, request: &LlmRequest) -> Option<LlmRequest> {
+        if request.settings.model.as_deref() == Some(self.model.as_str()) {
+            return None;
+        }
+        let mut request = request.clone();
+        request.settings.model = Some(self.model.clone());
+        Some(request)
+    }
+}
+
+/// Errors another model might not run into: overload, rate limits, an unknown model.
+/// The upstream's circuit breaker is shared by both models, so `Unavailable` isn't one.
+fn should_fall_back(error: &LlmError, streaming: bool) -> bool {
+    match error {
+        LlmError::Http { status, .. } => status.is_server_error() || status.as_u16() == 404,
+        LlmError::RateLimited { .. } => true,
+        // A streamed answer may have failed halfway, after some of it was already shown.
+        LlmError::Request(_) => !streaming,
+        _ => false,
+    }
+}
+
+#[async_trait]
+impl LlmProvider for FallbackProvider {
+    fn name(&self) -> String {
+        format!("{} (fallback {})", self.inner.name(), self.model)
+    }
+
+    async fn generate(&self, request: &LlmRequest) -> Result<LlmResponse, LlmError> {
+        match self.inner.generate(request).await {
+            Err(e) if should_fall_back(&e, false) => match self.fallback_request(request) {
+                Some(fallback) => {
+                    eprintln!("[WARN] {} failed ({}), retrying with the fallback model {}.", self.inner.name(), e, self.model);
+                    self.inner.generate(&fallback).await
+                }
+                None => Err(e),
+            },
+            result => result,
+        }
+    }
+
+    async fn generate_stream(&self, request: &LlmRequest, deltas: UnboundedSender<String>) -> Result<LlmResponse, LlmError> {
+        match self.inner.generate_stream(request, deltas.clone()).await {
+            Err(e) if should_fall_back(&e, true) => match self.fallback_request(request) {
+                Some(fallback) => {
+                    eprintln!("[WARN] {} failed ({}), retrying with the fallback model {}.", self.inner.name(), e, self.model);
+                    self.inner.generate_stream(&fallback, deltas).await
+                }
+                None => Err(e),
+            },
+            result => result,
+        }
+    }
+
+    /// The fallback model only generates text, so embeddings always use the inner provider.
+    async fn embed(&self, text: &str, task: EmbeddingTask) -> Result<Vec<f32>, LlmError> {
+        self.inner.embed(text, task).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{EmbeddingTask, FinishReason, TokenUsage};
+    use reqwest::StatusCode;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// Fails with `error` on every model but "fallback", and remembers which models were asked.
+    struct Flaky {
+        error: fn() -> LlmError,
+        models: Mutex<Vec<Option<String>>>,
+    }
+
+    #[async_trait]
+    impl LlmProvider for Flaky {
+        fn name(&self) -> String {
+            "flaky".to_string()
+        }
+
+        async fn generate(&self, request: &LlmRequest) -> Result<LlmResponse, LlmError> {
+            self.models.lock().unwrap().push(request.settings.model.clone());
+            if request.settings.model.as_deref() == Some("fallback") {
+                Ok(LlmResponse { text: "ok".to_string(), tool_calls: Vec::new(), finish_reason: Some(FinishReason::Stop), usage: TokenUsage::default() })
+            } else {
+                Err((self.error)())
+            }
+        }
+
+        async fn embed(&self, _text: &str, _task: EmbeddingTask) -> Result<Vec<f32>, LlmError> {
+            Err((self.error)())
+        }
+    }
+
+    /// The models asked for, in order, when a request fails with `error`.
+    async fn models_tried(error: fn() -> LlmError) -> Vec<Option<String>> {
+        let flaky = Arc::new(Flaky { error, models: Mutex::new(Vec::new()) });
+        let provider = FallbackProvider::new(flaky.clone(), "fallback".to_string());
+        let _ = provider.generate(&LlmRequest::default()).await;
+        let models = flaky.models.lock().unwrap().clone();
+        models
+    }
+
+    #[tokio::test]
+    async fn falls_back_when_overloaded_rate_limited_or_model_unknown() {
+        let overloaded = || LlmError::Http { status: StatusCode::SERVICE_UNAVAILABLE, message: "overloaded".to_string() };
+        let rate_limited = || LlmError::RateLimited { retry_after: None };
+        let unknown_model = || LlmError::Http { status: StatusCode::NOT_FOUND, message: "no such model".to_string() };
+        for error in [overloaded as fn() -> LlmError, rate_limited, unknown_model] {
+            assert_eq!(models_tried(error).await, vec![None, Some("fallback".to_string())]);
+        }
+    }
+
+    #[tokio::test]
+    async fn does_not_fall_back_on_other_errors() {
+        let bad_request = || LlmError::Http { status: StatusCode::BAD_REQUEST, message: "bad".to_string() };
+        let blocked = || LlmError::SafetyBlocked { category: None, reason: "SAFETY".to_string() };
+        let breaker_open = || LlmError::Unavailable { retry_in: Duration::from_secs(5) };
+        let quota = || LlmError::QuotaExceeded { message: "used up".to_string() };
+        for error in [bad_request as fn() -> LlmError, blocked, breaker_open, quota] {
+            assert_eq!(models_tried(error).await, vec![None]);
+        }
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_a_request_for_the_fallback_model() {
+        let flaky = Arc::new(Flaky { error: || LlmError::RateLimited { retry_after: None }, models: Mutex::new(Vec::new()) });
+        let provider = FallbackProvider::new(flaky.clone(), "other".to_string());
+        let request = LlmRequest { settings: crate::llm::GenerationSettings { model: Some("other".to_string()), ..Default::default() }, ..Default::default() };
+        assert!(provider.generate(&request).await.is_err());
+        assert_eq!(flaky.models.lock().unwrap().len(), 1);
+    }
+}
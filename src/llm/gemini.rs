@@ -0,0 +1,463 @@
+//! Google Gemini backend, with typed models for the `generateContent` REST API.
+//! See https://ai.google.dev/api/generate-content for the full schema.
+
+use super::{check_status, for_each_sse_data, ChatPart, EmbeddingTask, LlmError, LlmProvider, LlmRequest, LlmResponse, Role, TokenUsage, ToolCall, ToolChoice};
+use crate::upstream::Upstream;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serenity::async_trait;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub const DEFAULT_GEMINI_MODEL: &str = "gemini-2.5-flash";
+/// Used when `DEFAULT_GEMINI_MODEL` keeps failing, see `llm::fallback`.
+pub const DEFAULT_GEMINI_FALLBACK_MODEL: &str = "gemini-2.5-flash-lite";
+pub const DEFAULT_GEMINI_EMBEDDING_MODEL: &str = "gemini-embedding-001";
+/// Embeddings are cut down to this many dimensions, plenty for short texts.
+const EMBEDDING_DIMENSIONS: u32 = 768;
+const GEMINI_BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta/models";
+
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateContentRequest {
+    pub contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<Content>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<ToolConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation_config: Option<GenerationConfig>,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_mime_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_schema: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+}
+
+/// Body of `embedContent`, see https://ai.google.dev/api/embeddings.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EmbedContentRequest {
+    pub content: Content,
+    /// "RETRIEVAL_DOCUMENT" or "RETRIEVAL_QUERY".
+    pub task_type: &'static str,
+    pub output_dimensionality: u32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EmbedContentResponse {
+    pub embedding: ContentEmbedding,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ContentEmbedding {
+    #[serde(default)]
+    pub values: Vec<f32>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Tool {
+    pub function_declarations: Vec<FunctionDeclaration>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolConfig {
+    pub function_calling_config: FunctionCallingConfig,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FunctionCallingConfig {
+    /// "AUTO", "ANY" or "NONE".
+    pub mode: &'static str,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Content {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub parts: Vec<Part>,
+}
+
+impl Content {
+    pub fn model(text: impl Into<String>) -> Self {
+        Content { role: Some("model".to_string()), parts: vec![Part::text(text)] }
+    }
+
+    /// System instructions carry no role, only parts.
+    pub fn system(text: impl Into<String>) -> Self {
+        Content { role: None, parts: vec![Part::text(text)] }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Part {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inline_data: Option<Blob>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<FunctionCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_response: Option<FunctionResponse>,
+}
+
+impl Part {
+    pub fn text(text: impl Into<String>) -> Self {
+        Part { text: Some(text.into()), ..Default::default() }
+    }
+}
+
+/// Raw bytes sent inline with a request, base64 encoded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Blob {
+    pub mime_type: String,
+    pub data: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub args: serde_json::Value,
+}
+
+/// The result of a `FunctionCall`. `response` has to be a JSON object.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunctionResponse {
+    pub name: String,
+    pub response: serde_json::Value,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateContentResponse {
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+    pub prompt_feedback: Option<PromptFeedback>,
+    pub usage_metadata: Option<UsageMetadata>,
+}
+
+impl GenerateContentResponse {
+    /// All text parts of the first candidate joined together, if there are any.
+    pub fn text(&self) -> Option<String> {
+        let text = self.raw_text();
+        if text.trim().is_empty() { None } else { Some(text) }
+    }
+
+    /// Like `text`, but keeps whitespace-only text. Streamed chunks can legitimately be just a space.
+    fn raw_text(&self) -> String {
+        self.candidates.first()
+            .and_then(|c| c.content.as_ref())
+            .map(|content| content.parts.iter().filter_map(|p| p.text.as_deref()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Function calls in the first candidate.
+    pub fn function_calls(&self) -> Vec<ToolCall> {
+        self.candidates.first()
+            .and_then(|c| c.content.as_ref())
+            .map(|content| content.parts.iter().filter_map(|p| p.function_call.as_ref()).map(|call| ToolCall {
+                id: None,
+                name: call.name.clone(),
+                args: call.args.clone(),
+            }).collect())
+            .unwrap_or_default()
+    }
+
+    /// The reply as a provider-neutral response, or the reason there isn't one.
+    pub fn into_llm_response(self) -> Result<LlmResponse, LlmError> {
+        let finish_reason = self.candidates.first().and_then(|c| c.finish_reason);
+        let usage = self.usage_metadata.unwrap_or_default();
+        let tool_calls = self.function_calls();
+
+        if let Some(text) = self.text().or_else(|| (!tool_calls.is_empty()).then(String::new)) {
+            return Ok(LlmResponse {
+                text,
+                tool_calls,
+                finish_reason: finish_reason.map(FinishReason::to_llm),
+                usage: TokenUsage {
+                    prompt_tokens: usage.prompt_token_count,
+                    response_tokens: usage.candidates_token_count,
+                },
+            });
+        }
+
+        let block_reason = self.prompt_feedback.as_ref().and_then(|f| f.block_reason.clone());
+        let safety_finish = finish_reason.map(FinishReason::to_llm) == Some(super::FinishReason::Safety);
+
+        if block_reason.is_some() || safety_finish {
+            let reason = block_reason.unwrap_or_else(|| format!("{:?}", finish_reason.unwrap_or(FinishReason::Unspecified)));
+            Err(LlmError::SafetyBlocked { category: self.blocked_categories().into_iter().next(), reason })
+        } else {
+            Err(LlmError::EmptyResponse { finish_reason: finish_reason.map(FinishReason::to_llm) })
+        }
+    }
+
+    /// Safety categories that caused the prompt or the first candidate to be blocked.
+    pub fn blocked_categories(&self) -> Vec<String> {
+        let prompt_ratings = self.prompt_feedback.iter().flat_map(|f| f.safety_ratings.iter());
+        let candidate_ratings = self.candidates.iter().take(1).flat_map(|c| c.safety_ratings.iter());
+        prompt_ratings.chain(candidate_ratings)
+            .filter(|r| r.blocked || r.probability == "HIGH")
+            .map(|r| r.category.clone())
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Candidate {
+    pub content: Option<Content>,
+    pub finish_reason: Option<FinishReason>,
+    #[serde(default)]
+    pub safety_ratings: Vec<SafetyRating>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum FinishReason {
+    Stop,
+    MaxTokens,
+    Safety,
+    Recitation,
+    Language,
+    Blocklist,
+    ProhibitedContent,
+    Spii,
+    MalformedFunctionCall,
+    Other,
+    #[serde(other)]
+    Unspecified,
+}
+
+impl FinishReason {
+    fn to_llm(self) -> super::FinishReason {
+        match self {
+            FinishReason::Stop => super::FinishReason::Stop,
+            FinishReason::MaxTokens => super::FinishReason::MaxTokens,
+            FinishReason::Safety | FinishReason::Blocklist | FinishReason::ProhibitedContent | FinishReason::Spii => super::FinishReason::Safety,
+            _ => super::FinishReason::Other,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptFeedback {
+    pub block_reason: Option<String>,
+    #[serde(default)]
+    pub safety_ratings: Vec<SafetyRating>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SafetyRating {
+    pub category: String,
+    pub probability: String,
+    #[serde(default)]
+    pub blocked: bool,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageMetadata {
+    #[serde(default)]
+    pub prompt_token_count: u32,
+    #[serde(default)]
+    pub candidates_token_count: u32,
+}
+
+pub struct GeminiProvider {
+    upstream: Upstream,
+    model: String,
+    embedding_model: String,
+}
+
+impl GeminiProvider {
+    pub fn new(upstream: Upstream, model: String, embedding_model: String) -> Self {
+        GeminiProvider { upstream, model, embedding_model }
+    }
+
+    fn to_wire(request: &LlmRequest) -> GenerateContentRequest {
+        let contents = request.messages.iter().map(|message| Content {
+            role: Some(match message.role {
+                Role::User => "user".to_string(),
+                Role::Model => "model".to_string(),
+            }),
+            parts: message.parts.iter().map(|part| match part {
+                ChatPart::Text(text) => Part::text(text.clone()),
+                ChatPart::InlineData { mime_type, data } => Part {
+                    inline_data: Some(Blob {
+                        mime_type: mime_type.clone(),
+                        data: base64::engine::general_purpose::STANDARD.encode(data),
+                    }),
+                    ..Default::default()
+                },
+                ChatPart::ToolCall(call) => Part {
+                    function_call: Some(FunctionCall { name: call.name.clone(), args: call.args.clone() }),
+                    ..Default::default()
+                },
+                ChatPart::ToolResult { name, result, .. } => Part {
+                    function_response: Some(FunctionResponse { name: name.clone(), response: result.clone() }),
+                    ..Default::default()
+                },
+            }).collect(),
+        }).collect();
+
+        let tools = if request.tools.is_empty() {
+            Vec::new()
+        } else {
+            vec![Tool {
+                function_declarations: request.tools.iter().map(|tool| FunctionDeclaration {
+                    name: tool.name.to_string(),
+                    description: tool.description.to_string(),
+                    parameters: tool.parameters.clone(),
+                }).collect(),
+            }]
+        };
+        let tool_config = match request.tool_choice {
+            ToolChoice::None if !tools.is_empty() => Some(ToolConfig { function_calling_config: FunctionCallingConfig { mode: "NONE" } }),
+            _ => None,
+        };
+
+        let generation_config = GenerationConfig {
+            response_mime_type: request.response_schema.as_ref().map(|_| "application/json"),
+            response_schema: request.response_schema.clone(),
+            temperature: request.settings.temperature,
+            top_p: request.settings.top_p,
+            max_output_tokens: request.settings.max_output_tokens,
+        };
+        let generation_config = Some(generation_config).filter(|config| *config != GenerationConfig::default());
+
+        GenerateContentRequest {
+            contents,
+            system_instruction: request.system_instruction.as_deref().map(Content::system),
+            tools,
+            tool_config,
+            generation_config,
+        }
+    }
+
+    /// Sends `body` to the request's model, or the provider's model if it doesn't pick one.
+    async fn post(&self, method: &str, request: &LlmRequest, body: &GenerateContentRequest) -> Result<reqwest::Response, LlmError> {
+        self.post_to(request.settings.model.as_deref().unwrap_or(&self.model), method, body).await
+    }
+
+    async fn post_to<B: Serialize>(&self, model: &str, method: &str, body: &B) -> Result<reqwest::Response, LlmError> {
+        let url = format!("{}/{}:{}", GEMINI_BASE_URL, model, method);
+        let response = self.upstream.send(|http| {
+            http.post(&url)
+                .header("x-goog-api-key", &self.upstream.api_key)
+                .json(body)
+        }).await?;
+        check_status(response).await
+    }
+}
+
+#[async_trait]
+impl LlmProvider for GeminiProvider {
+    fn name(&self) -> String {
+        format!("gemini:{}", self.model)
+    }
+
+    async fn generate(&self, request: &LlmRequest) -> Result<LlmResponse, LlmError> {
+        let response = self.post("generateContent", request, &Self::to_wire(request)).await?;
+        let body = response.bytes().await?;
+        let response = serde_json::from_slice::<GenerateContentResponse>(&body).map_err(|e| LlmError::Decode(e.to_string()))?;
+        response.into_llm_response()
+    }
+
+    /// Calls `streamGenerateContent` over server-sent events and folds the chunks back into
+    /// a single response, so it can be handled exactly like a `generate` result.
+    async fn generate_stream(&self, request: &LlmRequest, deltas: UnboundedSender<String>) -> Result<LlmResponse, LlmError> {
+        let response = self.post("streamGenerateContent?alt=sse", request, &Self::to_wire(request)).await?;
+
+        let mut aggregate = StreamAggregate::default();
+        for_each_sse_data(response, |data| aggregate.push(data, &deltas)).await?;
+        aggregate.finish().into_llm_response()
+    }
+
+    async fn embed(&self, text: &str, task: EmbeddingTask) -> Result<Vec<f32>, LlmError> {
+        let body = EmbedContentRequest {
+            content: Content { role: None, parts: vec![Part::text(text)] },
+            task_type: match task {
+                EmbeddingTask::Document => "RETRIEVAL_DOCUMENT",
+                EmbeddingTask::Query => "RETRIEVAL_QUERY",
+            },
+            output_dimensionality: EMBEDDING_DIMENSIONS,
+        };
+        let response = self.post_to(&self.embedding_model, "embedContent", &body).await?;
+        let body = response.bytes().await?;
+        let response = serde_json::from_slice::<EmbedContentResponse>(&body).map_err(|e| LlmError::Decode(e.to_string()))?;
+        Some(response.embedding.values).filter(|values| !values.is_empty())
+            .ok_or_else(|| LlmError::Decode("no embedding in the response".to_string()))
+    }
+}
+
+#[derive(Default)]
+struct StreamAggregate {
+    text: String,
+    finish_reason: Option<FinishReason>,
+    safety_ratings: Vec<SafetyRating>,
+    prompt_feedback: Option<PromptFeedback>,
+    usage_metadata: Option<UsageMetadata>,
+}
+
+impl StreamAggregate {
+    fn push(&mut self, data: &str, deltas: &UnboundedSender<String>) -> Result<(), LlmError> {
+        let chunk: GenerateContentResponse = serde_json::from_str(data)
+            .map_err(|e| LlmError::Decode(e.to_string()))?;
+
+        let delta = chunk.raw_text();
+        if !delta.is_empty() {
+            self.text.push_str(&delta);
+            // The receiver going away only means nobody is watching the progress.
+            let _ = deltas.send(delta);
+        }
+        if let Some(candidate) = chunk.candidates.into_iter().next() {
+            self.finish_reason = candidate.finish_reason.or(self.finish_reason);
+            if !candidate.safety_ratings.is_empty() {
+                self.safety_ratings = candidate.safety_ratings;
+            }
+        }
+        self.prompt_feedback = chunk.prompt_feedback.or(self.prompt_feedback.take());
+        self.usage_metadata = chunk.usage_metadata.or(self.usage_metadata);
+        Ok(())
+    }
+
+    fn finish(self) -> GenerateContentResponse {
+        GenerateContentResponse {
+            candidates: vec![Candidate {
+                content: Some(Content::model(self.text)),
+                finish_reason: self.finish_reason,
+                safety_ratings: self.safety_ratings,
+            }],
+            prompt_feedback: self.prompt_feedback,
+            usage_metadata: self.usage_metadata,
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! A deterministic backend that never leaves the process. Useful for running the bot and its
+//! tests without an API key: the reply only depends on the request.
+
+use super::{ChatPart, EmbeddingTask, FinishReason, LlmError, LlmProvider, LlmRequest, LlmResponse, TokenUsage};
+use serenity::async_trait;
+use tokio::sync::mpsc::UnboundedSender;
+
+pub struct MockProvider;
+
+/// Length of the mock embeddings.
+const EMBEDDING_DIMENSIONS: usize = 64;
+
+impl MockProvider {
+    fn reply(request: &LlmRequest) -> LlmResponse {
+        let last_message = request.messages.last();
+        let attachments = last_message.map_or(0, |m| m.parts.iter().filter(|p| matches!(p, ChatPart::InlineData { .. })).count());
+        let text = format!(
+            "[mock reply to {} message(s), {} attachment(s)] {}",
+            request.messages.len(), attachments, last_message.map(|m| m.text()).unwrap_or_default()
+        );
+
+        let prompt_words: usize = request.system_instruction.iter().map(|s| s.split_whitespace().count()).sum::<usize>()
+            + request.messages.iter().map(|m| m.text().split_whitespace().count()).sum::<usize>();
+        LlmResponse {
+            usage: TokenUsage { prompt_tokens: prompt_words as u32, response_tokens: text.split_whitespace().count() as u32 },
+            text,
+            tool_calls: Vec::new(),
+            finish_reason: Some(FinishReason::Stop),
+        }
+    }
+
+    /// A bag of words: every lowercased word is hashed (FNV-1a) into one of the dimensions,
+    /// so texts sharing words come out similar.
+    fn embedding(text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0; EMBEDDING_DIMENSIONS];
+        for word in text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()) {
+            let hash = word.to_lowercase().bytes().fold(0xcbf29ce484222325u64, |hash, b| (hash ^ b as u64).wrapping_mul(0x100000001b3));
+            vector[(hash % EMBEDDING_DIMENSIONS as u64) as usize] += 1.0;
+        }
+        vector
+    }
+}
+
+#[async_trait]
+impl LlmProvider for MockProvider {
+    fn name(&self) -> String {
+        "mock".to_string()
+    }
+
+    async fn generate(&self, request: &LlmRequest) -> Result<LlmResponse, LlmError> {
+        Ok(Self::reply(request))
+    }
+
+    /// Streams the reply word by word, so progressive edits can be exercised too.
+    async fn generate_stream(&self, request: &LlmRequest, deltas: UnboundedSender<String>) -> Result<LlmResponse, LlmError> {
+        let response = Self::reply(request);
+        for word in response.text.split_inclusive(' ') {
+            let _ = deltas.send(word.to_string());
+        }
+        Ok(response)
+    }
+
+    async fn embed(&self, text: &str, _task: EmbeddingTask) -> Result<Vec<f32>, LlmError> {
+        Ok(Self::embedding(text))
+    }
+}
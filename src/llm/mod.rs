@@ -0,0 +1,294 @@
+//! The language model behind Nuggies. Commands only talk to the `LlmProvider` trait; which
+//! backend answers (Gemini, an OpenAI-compatible server such as Ollama or llama.cpp, or the
+//! deterministic mock) is picked with `LLM_PROVIDER` at startup.
+
+pub mod fallback;
+pub mod gemini;
+pub mod mock;
+pub mod openai;
+
+use crate::upstream::{self, SendError};
+use reqwest::StatusCode;
+use serde_json::Value;
+use serenity::async_trait;
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    User,
+    Model,
+}
+
+#[derive(Debug, Clone)]
+pub enum ChatPart {
+    Text(String),
+    /// Raw file contents such as an image, sent alongside the text.
+    InlineData { mime_type: String, data: Vec<u8> },
+    /// The model asking the bot to run one of the request's tools.
+    ToolCall(ToolCall),
+    /// What running a `ToolCall` returned, sent back in a user message.
+    ToolResult { id: Option<String>, name: String, result: Value },
+}
+
+#[derive(Debug, Clone)]
+pub struct ToolCall {
+    /// Set by backends that match results to calls by ID (OpenAI), `None` for Gemini.
+    pub id: Option<String>,
+    pub name: String,
+    pub args: Value,
+}
+
+/// A function the model may call. `parameters` is a JSON schema object, `None` for
+/// functions without arguments (Gemini rejects objects without properties).
+#[derive(Debug, Clone)]
+pub struct ToolDeclaration {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub parameters: Option<Value>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ToolChoice {
+    /// The model decides whether to call a tool.
+    #[default]
+    Auto,
+    /// The model has to answer with text, even though tools are declared.
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub parts: Vec<ChatPart>,
+}
+
+impl ChatMessage {
+    pub fn user(text: impl Into<String>) -> Self {
+        ChatMessage { role: Role::User, parts: vec![ChatPart::Text(text.into())] }
+    }
+
+    pub fn model(text: impl Into<String>) -> Self {
+        ChatMessage { role: Role::Model, parts: vec![ChatPart::Text(text.into())] }
+    }
+
+    /// All text parts joined together.
+    pub fn text(&self) -> String {
+        self.parts.iter().filter_map(|part| match part {
+            ChatPart::Text(text) => Some(text.as_str()),
+            _ => None,
+        }).collect()
+    }
+}
+
+/// Model and sampling settings for a request. `None` keeps the provider's model or the
+/// backend's default.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GenerationSettings {
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_output_tokens: Option<u32>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct LlmRequest {
+    pub system_instruction: Option<String>,
+    pub messages: Vec<ChatMessage>,
+    pub tools: Vec<ToolDeclaration>,
+    pub tool_choice: ToolChoice,
+    /// If set, the answer is JSON matching this JSON schema instead of free text.
+    pub response_schema: Option<Value>,
+    pub settings: GenerationSettings,
+}
+
+/// What an embedding is for. Gemini tunes embeddings for being stored or for searching;
+/// other backends ignore it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmbeddingTask {
+    Document,
+    Query,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinishReason {
+    Stop,
+    MaxTokens,
+    Safety,
+    Other,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub response_tokens: u32,
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: TokenUsage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.response_tokens += other.response_tokens;
+    }
+}
+
+#[derive(Debug)]
+pub struct LlmResponse {
+    /// Only empty when the model called tools instead of answering; providers turn any other
+    /// empty answer into `LlmError::EmptyResponse`.
+    pub text: String,
+    pub tool_calls: Vec<ToolCall>,
+    pub finish_reason: Option<FinishReason>,
+    pub usage: TokenUsage,
+}
+
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    /// Provider and model, for logging.
+    fn name(&self) -> String;
+
+    async fn generate(&self, request: &LlmRequest) -> Result<LlmResponse, LlmError>;
+
+    /// Like `generate`, but sends each piece of text to `deltas` as soon as it is known.
+    /// Providers without streaming support send the whole answer in one piece.
+    async fn generate_stream(&self, request: &LlmRequest, deltas: UnboundedSender<String>) -> Result<LlmResponse, LlmError> {
+        let response = self.generate(request).await?;
+        let _ = deltas.send(response.text.clone());
+        Ok(response)
+    }
+
+    /// Turns `text` into a vector for similarity search, using the provider's embedding model.
+    async fn embed(&self, text: &str, task: EmbeddingTask) -> Result<Vec<f32>, LlmError>;
+}
+
+/// Everything that can go wrong between asking the model something and getting text back.
+#[derive(Debug)]
+pub enum LlmError {
+    /// The request never got a response (DNS, TLS, timeout, ...), even after retrying.
+    Request(reqwest::Error),
+    /// The backend has failed repeatedly, so the circuit breaker is not letting requests through.
+    Unavailable { retry_in: Duration },
+    /// The backend answered with a non-success status other than 429.
+    Http { status: StatusCode, message: String },
+    /// The backend answered with 429, optionally telling us when to come back.
+    RateLimited { retry_after: Option<Duration> },
+    /// The prompt or the answer was blocked by the safety filters.
+    SafetyBlocked { category: Option<String>, reason: String },
+    /// The backend answered, but without any text.
+    EmptyResponse { finish_reason: Option<FinishReason> },
+    /// The response body wasn't the JSON we expected.
+    Decode(String),
+    /// The call was never made because the user or guild used up its daily token quota.
+    QuotaExceeded { message: String },
+}
+
+impl LlmError {
+    /// A short explanation of the error that can be shown to Discord users.
+    pub fn user_reason(&self) -> String {
+        match self {
+            LlmError::Request(e) if e.is_timeout() => "The AI service took too long to answer.".to_string(),
+            LlmError::Request(_) => "I couldn't reach the AI service.".to_string(),
+            LlmError::Unavailable { retry_in } => {
+                format!("The AI service seems to be down, so I'm taking a short nap. Try again in about {} seconds.", retry_in.as_secs().max(1))
+            }
+            LlmError::Http { status, .. } if status.is_server_error() => {
+                format!("The AI service seems to be having problems right now (HTTP {}).", status.as_u16())
+            }
+            LlmError::Http { status, .. } => format!("The AI service rejected the request (HTTP {}).", status.as_u16()),
+            LlmError::RateLimited { retry_after: Some(wait) } => {
+                format!("I've hit my AI rate limit. Try again in about {} seconds.", wait.as_secs().max(1))
+            }
+            LlmError::RateLimited { retry_after: None } => "I've hit my AI rate limit. Try again in a bit.".to_string(),
+            LlmError::SafetyBlocked { category: Some(category), .. } => {
+                format!("The safety filter blocked that ({}).", pretty_category(category))
+            }
+            LlmError::SafetyBlocked { category: None, reason } => {
+                format!("The safety filter blocked that ({}).", reason.to_lowercase().replace('_', " "))
+            }
+            LlmError::EmptyResponse { finish_reason: Some(FinishReason::MaxTokens) } => {
+                "The answer ran out of room before any text came out.".to_string()
+            }
+            LlmError::EmptyResponse { .. } => "The AI came back with an empty answer.".to_string(),
+            LlmError::Decode(_) => "The AI service sent back something I couldn't make sense of.".to_string(),
+            LlmError::QuotaExceeded { message } => message.clone(),
+        }
+    }
+}
+
+impl fmt::Display for LlmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LlmError::Request(e) => write!(f, "request failed: {}", e),
+            LlmError::Unavailable { retry_in } => write!(f, "circuit breaker open for another {:?}", retry_in),
+            LlmError::Http { status, message } => write!(f, "HTTP {}: {}", status, message),
+            LlmError::RateLimited { retry_after } => write!(f, "rate limited (retry after {:?})", retry_after),
+            LlmError::SafetyBlocked { category, reason } => write!(f, "blocked by safety filter: {} ({:?})", reason, category),
+            LlmError::EmptyResponse { finish_reason } => write!(f, "empty response (finish reason {:?})", finish_reason),
+            LlmError::Decode(e) => write!(f, "could not decode response: {}", e),
+            LlmError::QuotaExceeded { message } => write!(f, "quota exceeded: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for LlmError {}
+
+impl From<reqwest::Error> for LlmError {
+    fn from(e: reqwest::Error) -> Self {
+        LlmError::Request(e)
+    }
+}
+
+impl From<SendError> for LlmError {
+    fn from(e: SendError) -> Self {
+        match e {
+            SendError::CircuitOpen { retry_in } => LlmError::Unavailable { retry_in },
+            SendError::Request(e) => LlmError::Request(e),
+        }
+    }
+}
+
+/// Turns "HARM_CATEGORY_DANGEROUS_CONTENT" into "dangerous content".
+fn pretty_category(category: &str) -> String {
+    category.trim_start_matches("HARM_CATEGORY_").to_lowercase().replace('_', " ")
+}
+
+/// Maps 429 and other non-success statuses to the matching `LlmError`. Both Gemini and
+/// OpenAI-style servers put a human readable message at `error.message`.
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, LlmError> {
+    let status = response.status();
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return Err(LlmError::RateLimited { retry_after: upstream::retry_after(&response) });
+    }
+    if !status.is_success() {
+        let body = response.bytes().await?;
+        let message = serde_json::from_slice::<serde_json::Value>(&body).ok()
+            .and_then(|v| v["error"]["message"].as_str().map(|m| m.to_string()))
+            .unwrap_or_else(|| String::from_utf8_lossy(&body).chars().take(200).collect());
+        return Err(LlmError::Http { status, message });
+    }
+    Ok(response)
+}
+
+/// Reads a server-sent events body and calls `on_data` with the payload of every `data:` line.
+async fn for_each_sse_data<F>(mut response: reqwest::Response, mut on_data: F) -> Result<(), LlmError>
+where
+    F: FnMut(&str) -> Result<(), LlmError>,
+{
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut handle_event = |event: &[u8]| -> Result<(), LlmError> {
+        let event = String::from_utf8_lossy(event);
+        for data in event.lines().filter_map(|line| line.strip_prefix("data:")) {
+            on_data(data.trim())?;
+        }
+        Ok(())
+    };
+
+    while let Some(chunk) = response.chunk().await? {
+        buffer.extend(chunk.iter().filter(|b| **b != b'\r'));
+        while let Some(end) = buffer.windows(2).position(|w| w == b"\n\n") {
+            let event: Vec<u8> = buffer.drain(..end + 2).collect();
+            handle_event(&event)?;
+        }
+    }
+    handle_event(&buffer)
+}
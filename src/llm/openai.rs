@@ -0,0 +1,356 @@
+//! Backend for servers that speak the OpenAI chat completions API, e.g. a local Ollama
+//! (`http://localhost:11434/v1`) or llama.cpp server.
+
+use super::{check_status, for_each_sse_data, ChatMessage, ChatPart, EmbeddingTask, FinishReason, LlmError, LlmProvider, LlmRequest, LlmResponse, Role, TokenUsage, ToolCall, ToolChoice};
+use crate::upstream::Upstream;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serenity::async_trait;
+use tokio::sync::mpsc::UnboundedSender;
+
+#[derive(Serialize, Debug)]
+struct ChatCompletionRequest {
+    model: String,
+    messages: Vec<WireMessage>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<WireTool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+struct WireTool {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    function: WireFunction,
+}
+
+#[derive(Serialize, Debug)]
+struct WireFunction {
+    name: &'static str,
+    description: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parameters: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct WireToolCall {
+    #[serde(default)]
+    id: String,
+    #[serde(rename = "type", default = "function_kind")]
+    kind: String,
+    function: WireFunctionCall,
+}
+
+fn function_kind() -> String {
+    "function".to_string()
+}
+
+/// `arguments` is a JSON object encoded as a string.
+#[derive(Serialize, Deserialize, Debug)]
+struct WireFunctionCall {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    arguments: String,
+}
+
+#[derive(Serialize, Debug)]
+struct StreamOptions {
+    include_usage: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct WireMessage {
+    role: &'static str,
+    /// Only `None` for assistant messages that consist of tool calls.
+    content: Option<WireContent>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<WireToolCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+impl WireMessage {
+    fn new(role: &'static str, content: WireContent) -> Self {
+        WireMessage { role, content: Some(content), tool_calls: Vec::new(), tool_call_id: None }
+    }
+}
+
+/// Plain text, or a list of parts when the message carries images.
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+enum WireContent {
+    Text(String),
+    Parts(Vec<WireContentPart>),
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WireContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Serialize, Debug)]
+struct ImageUrl {
+    url: String,
+}
+
+/// Images become data URLs; text files are inlined as text. Other binary files (PDFs)
+/// can't be expressed in the chat completions format and are replaced by a note.
+fn wire_content(message: &ChatMessage) -> WireContent {
+    if !message.parts.iter().any(|part| matches!(part, ChatPart::InlineData { .. })) {
+        return WireContent::Text(message.text());
+    }
+
+    WireContent::Parts(message.parts.iter().filter_map(|part| Some(match part {
+        ChatPart::Text(text) => WireContentPart::Text { text: text.clone() },
+        ChatPart::InlineData { mime_type, data } if mime_type.starts_with("image/") => WireContentPart::ImageUrl {
+            image_url: ImageUrl { url: format!("data:{};base64,{}", mime_type, base64::engine::general_purpose::STANDARD.encode(data)) },
+        },
+        ChatPart::InlineData { mime_type, data } if mime_type.starts_with("text/") => WireContentPart::Text {
+            text: String::from_utf8_lossy(data).into_owned(),
+        },
+        ChatPart::InlineData { mime_type, .. } => WireContentPart::Text {
+            text: format!("[An attachment of type {} was omitted because this model can't read it.]", mime_type),
+        },
+        ChatPart::ToolCall(_) | ChatPart::ToolResult { .. } => return None,
+    })).collect())
+}
+
+/// Tool calls go into the assistant message's `tool_calls`, and every tool result becomes
+/// a message of its own with the "tool" role.
+fn wire_messages(message: &ChatMessage) -> Vec<WireMessage> {
+    let mut messages: Vec<WireMessage> = message.parts.iter().filter_map(|part| match part {
+        ChatPart::ToolResult { id, result, .. } => Some(WireMessage {
+            tool_call_id: id.clone(),
+            ..WireMessage::new("tool", WireContent::Text(result.to_string()))
+        }),
+        _ => None,
+    }).collect();
+    if !messages.is_empty() {
+        return messages;
+    }
+
+    let role = match message.role {
+        Role::User => "user",
+        Role::Model => "assistant",
+    };
+    let tool_calls: Vec<WireToolCall> = message.parts.iter().filter_map(|part| match part {
+        ChatPart::ToolCall(call) => Some(WireToolCall {
+            id: call.id.clone().unwrap_or_default(),
+            kind: function_kind(),
+            function: WireFunctionCall { name: call.name.clone(), arguments: call.args.to_string() },
+        }),
+        _ => None,
+    }).collect();
+    let content = wire_content(message);
+    let content = match &content {
+        WireContent::Text(text) if text.is_empty() && !tool_calls.is_empty() => None,
+        _ => Some(content),
+    };
+    messages.push(WireMessage { role, content, tool_calls, tool_call_id: None });
+    messages
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatCompletionResponse {
+    #[serde(default)]
+    choices: Vec<Choice>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Choice {
+    /// Set on regular responses.
+    message: Option<ChoiceContent>,
+    /// Set on streamed chunks.
+    delta: Option<ChoiceContent>,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChoiceContent {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<WireToolCall>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+struct Usage {
+    #[serde(default)]
+    prompt_tokens: u32,
+    #[serde(default)]
+    completion_tokens: u32,
+}
+
+#[derive(Serialize, Debug)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+struct EmbeddingResponse {
+    #[serde(default)]
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+}
+
+fn finish_reason(reason: &str) -> FinishReason {
+    match reason {
+        "stop" => FinishReason::Stop,
+        "length" => FinishReason::MaxTokens,
+        "content_filter" => FinishReason::Safety,
+        _ => FinishReason::Other,
+    }
+}
+
+/// Builds the final response, applying the same empty/safety rules as the Gemini backend.
+fn into_llm_response(text: String, tool_calls: Vec<WireToolCall>, reason: Option<String>, usage: Option<Usage>) -> Result<LlmResponse, LlmError> {
+    let reason = reason.as_deref().map(finish_reason);
+    let tool_calls: Vec<ToolCall> = tool_calls.into_iter().enumerate().map(|(i, call)| ToolCall {
+        // Some servers leave the ID out, but it's needed to match the result to the call.
+        id: Some(if call.id.is_empty() { format!("call_{}", i) } else { call.id }),
+        args: serde_json::from_str(&call.function.arguments).unwrap_or_else(|_| serde_json::json!({})),
+        name: call.function.name,
+    }).collect();
+    if text.trim().is_empty() && tool_calls.is_empty() {
+        return Err(if reason == Some(FinishReason::Safety) {
+            LlmError::SafetyBlocked { category: None, reason: "CONTENT_FILTER".to_string() }
+        } else {
+            LlmError::EmptyResponse { finish_reason: reason }
+        });
+    }
+
+    let usage = usage.unwrap_or_default();
+    Ok(LlmResponse {
+        text,
+        tool_calls,
+        finish_reason: reason,
+        usage: TokenUsage { prompt_tokens: usage.prompt_tokens, response_tokens: usage.completion_tokens },
+    })
+}
+
+pub struct OpenAiProvider {
+    upstream: Upstream,
+    base_url: String,
+    model: String,
+    embedding_model: String,
+}
+
+impl OpenAiProvider {
+    pub fn new(upstream: Upstream, base_url: String, model: String, embedding_model: String) -> Self {
+        OpenAiProvider { upstream, base_url: base_url.trim_end_matches('/').to_string(), model, embedding_model }
+    }
+
+    fn to_wire(&self, request: &LlmRequest, stream: bool) -> ChatCompletionRequest {
+        let system = request.system_instruction.iter().map(|text| WireMessage::new("system", WireContent::Text(text.clone())));
+        let messages = request.messages.iter().flat_map(wire_messages);
+        let tools: Vec<WireTool> = request.tools.iter().map(|tool| WireTool {
+            kind: "function",
+            function: WireFunction { name: tool.name, description: tool.description, parameters: tool.parameters.clone() },
+        }).collect();
+        let tool_choice = match request.tool_choice {
+            ToolChoice::None if !tools.is_empty() => Some("none"),
+            _ => None,
+        };
+
+        ChatCompletionRequest {
+            model: request.settings.model.clone().unwrap_or_else(|| self.model.clone()),
+            messages: system.chain(messages).collect(),
+            stream,
+            stream_options: if stream { Some(StreamOptions { include_usage: true }) } else { None },
+            tools,
+            tool_choice,
+            response_format: request.response_schema.as_ref().map(|schema| serde_json::json!({
+                "type": "json_schema",
+                "json_schema": { "name": "response", "schema": schema },
+            })),
+            temperature: request.settings.temperature,
+            top_p: request.settings.top_p,
+            max_tokens: request.settings.max_output_tokens,
+        }
+    }
+
+    async fn post<B: Serialize>(&self, path: &str, body: &B) -> Result<reqwest::Response, LlmError> {
+        let url = format!("{}/{}", self.base_url, path);
+        let response = self.upstream.send(|http| {
+            let builder = http.post(&url).json(body);
+            // Local servers usually don't need a key at all.
+            if self.upstream.api_key.is_empty() { builder } else { builder.bearer_auth(&self.upstream.api_key) }
+        }).await?;
+        check_status(response).await
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiProvider {
+    fn name(&self) -> String {
+        format!("openai:{}", self.model)
+    }
+
+    async fn generate(&self, request: &LlmRequest) -> Result<LlmResponse, LlmError> {
+        let response = self.post("chat/completions", &self.to_wire(request, false)).await?;
+        let body = response.bytes().await?;
+        let response = serde_json::from_slice::<ChatCompletionResponse>(&body).map_err(|e| LlmError::Decode(e.to_string()))?;
+
+        let (text, tool_calls, reason) = match response.choices.into_iter().next() {
+            Some(Choice { message: Some(message), finish_reason, .. }) => (message.content.unwrap_or_default(), message.tool_calls, finish_reason),
+            Some(choice) => (String::new(), Vec::new(), choice.finish_reason),
+            None => (String::new(), Vec::new(), None),
+        };
+        into_llm_response(text, tool_calls, reason, response.usage)
+    }
+
+    async fn generate_stream(&self, request: &LlmRequest, deltas: UnboundedSender<String>) -> Result<LlmResponse, LlmError> {
+        let response = self.post("chat/completions", &self.to_wire(request, true)).await?;
+
+        let mut text = String::new();
+        let mut reason = None;
+        let mut usage = None;
+        for_each_sse_data(response, |data| {
+            if data == "[DONE]" {
+                return Ok(());
+            }
+            let chunk: ChatCompletionResponse = serde_json::from_str(data).map_err(|e| LlmError::Decode(e.to_string()))?;
+            usage = chunk.usage.or(usage);
+            if let Some(choice) = chunk.choices.into_iter().next() {
+                if let Some(delta) = choice.delta.and_then(|d| d.content).filter(|d| !d.is_empty()) {
+                    text.push_str(&delta);
+                    let _ = deltas.send(delta);
+                }
+                reason = choice.finish_reason.or(reason.take());
+            }
+            Ok(())
+        }).await?;
+
+        // Tools are only offered to `generate`, so streamed answers are always plain text.
+        into_llm_response(text, Vec::new(), reason, usage)
+    }
+
+    /// Calls `/embeddings`, which has no notion of a task.
+    async fn embed(&self, text: &str, _task: EmbeddingTask) -> Result<Vec<f32>, LlmError> {
+        let response = self.post("embeddings", &EmbeddingRequest { model: &self.embedding_model, input: text }).await?;
+        let body = response.bytes().await?;
+        let response = serde_json::from_slice::<EmbeddingResponse>(&body).map_err(|e| LlmError::Decode(e.to_string()))?;
+        response.data.into_iter().next().map(|data| data.embedding).filter(|embedding| !embedding.is_empty())
+            .ok_or_else(|| LlmError::Decode("no embedding in the response".to_string()))
+    }
+}
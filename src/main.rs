@@ -1,20 +1,45 @@
+mod ambient;
+mod attachments;
+mod autotranslate;
+mod config;
+mod cooldown;
+mod flags;
+mod funfact;
+mod history;
+mod knowledge;
+mod llm;
+mod persona;
+mod prompts;
+mod reply;
+mod reply_chain;
+mod safety;
+mod slots;
+mod summarize;
+mod tools;
+mod translate;
+mod trigger;
+mod upstream;
+mod usage;
+
 use serenity::{
     async_trait,
     client::{Client, Context, EventHandler},
     model::{
         channel::Message,
         gateway::Ready,
-        id::{ChannelId, GuildId},
+        id::{ChannelId, GuildId, MessageId, UserId},
+        event::GuildMemberUpdateEvent,
+        user::User,
         application::{
-            interaction::{Interaction, InteractionResponseType},
-            command::{Command, CommandOptionType},
+            interaction::{Interaction, InteractionResponseType, application_command::{ApplicationCommandInteraction, ResolvedTarget}},
+            command::{Command, CommandOptionType, CommandType},
         },
         guild::Role,
         channel::Reaction,
+        Permissions,
     },
     prelude::GatewayIntents,
 };
-use reqwest::Client as HttpClient;
 use std::env;
 use std::sync::Arc;
 use rand::seq::SliceRandom;
@@ -27,6 +52,20 @@ use rand::Rng;
 use tokio_postgres::{NoTls, types::ToSql};
 use bb8::Pool;
 use bb8_postgres::PostgresConnectionManager;
+use config::BotConfig;
+use cooldown::Cooldowns;
+use prompts::Prompts;
+use history::HistoryTurn;
+use llm::{ChatMessage, ChatPart, LlmError, LlmProvider, LlmRequest};
+use llm::gemini::GeminiProvider;
+use llm::fallback::FallbackProvider;
+use llm::mock::MockProvider;
+use llm::openai::OpenAiProvider;
+use tools::ToolContext;
+use upstream::{CircuitBreaker, RetryPolicy, SendError, Upstream};
+use usage::Caller;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
 
 struct Handler;
 
@@ -54,6 +93,129 @@ impl Database {
                 )",
                 &[],
             ).await.expect("Failed to create users table");
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS conversation_history (
+                    id BIGSERIAL PRIMARY KEY,
+                    channel_id BIGINT NOT NULL,
+                    role TEXT NOT NULL,
+                    user_id BIGINT,
+                    author_name TEXT,
+                    content TEXT NOT NULL,
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                )",
+                &[],
+            ).await.expect("Failed to create conversation_history table");
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS conversation_history_channel_idx ON conversation_history (channel_id, id)",
+                &[],
+            ).await.expect("Failed to create conversation_history index");
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS guild_personas (
+                    guild_id BIGINT PRIMARY KEY,
+                    personality TEXT NOT NULL,
+                    updated_by BIGINT NOT NULL,
+                    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                )",
+                &[],
+            ).await.expect("Failed to create guild_personas table");
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS llm_usage (
+                    id BIGSERIAL PRIMARY KEY,
+                    user_id BIGINT NOT NULL,
+                    guild_id BIGINT,
+                    command TEXT NOT NULL,
+                    prompt_tokens INTEGER NOT NULL,
+                    response_tokens INTEGER NOT NULL,
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                )",
+                &[],
+            ).await.expect("Failed to create llm_usage table");
+            conn.execute(
+                "ALTER TABLE llm_usage ADD COLUMN IF NOT EXISTS counts_for_user BOOLEAN NOT NULL DEFAULT TRUE",
+                &[],
+            ).await.expect("Failed to add counts_for_user to llm_usage table");
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS llm_usage_user_idx ON llm_usage (user_id, created_at)",
+                &[],
+            ).await.expect("Failed to create llm_usage user index");
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS llm_usage_guild_idx ON llm_usage (guild_id, created_at)",
+                &[],
+            ).await.expect("Failed to create llm_usage guild index");
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS guild_flag_translation (
+                    guild_id BIGINT PRIMARY KEY,
+                    enabled BOOLEAN NOT NULL,
+                    updated_by BIGINT NOT NULL,
+                    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                )",
+                &[],
+            ).await.expect("Failed to create guild_flag_translation table");
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS flag_translations (
+                    message_id BIGINT NOT NULL,
+                    language TEXT NOT NULL,
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                    PRIMARY KEY (message_id, language)
+                )",
+                &[],
+            ).await.expect("Failed to create flag_translations table");
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS auto_translate_channels (
+                    channel_id BIGINT PRIMARY KEY,
+                    guild_id BIGINT NOT NULL,
+                    languages TEXT NOT NULL,
+                    webhook_id BIGINT,
+                    webhook_token TEXT,
+                    updated_by BIGINT NOT NULL,
+                    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                )",
+                &[],
+            ).await.expect("Failed to create auto_translate_channels table");
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS guild_facts (
+                    id BIGSERIAL PRIMARY KEY,
+                    guild_id BIGINT NOT NULL,
+                    fact TEXT NOT NULL,
+                    embedding REAL[] NOT NULL,
+                    created_by BIGINT NOT NULL,
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                )",
+                &[],
+            ).await.expect("Failed to create guild_facts table");
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS guild_facts_guild_idx ON guild_facts (guild_id)",
+                &[],
+            ).await.expect("Failed to create guild_facts index");
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS keyword_optout_channels (
+                    channel_id BIGINT PRIMARY KEY,
+                    guild_id BIGINT NOT NULL,
+                    updated_by BIGINT NOT NULL,
+                    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                )",
+                &[],
+            ).await.expect("Failed to create keyword_optout_channels table");
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS ambient_channels (
+                    channel_id BIGINT PRIMARY KEY,
+                    guild_id BIGINT NOT NULL,
+                    chance_percent INTEGER NOT NULL,
+                    cooldown_minutes INTEGER NOT NULL,
+                    updated_by BIGINT NOT NULL,
+                    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                )",
+                &[],
+            ).await.expect("Failed to create ambient_channels table");
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS serious_channels (
+                    channel_id BIGINT PRIMARY KEY,
+                    guild_id BIGINT NOT NULL,
+                    updated_by BIGINT NOT NULL,
+                    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                )",
+                &[],
+            ).await.expect("Failed to create serious_channels table");
         }
 
         Database { pool }
@@ -65,6 +227,11 @@ impl serenity::prelude::TypeMapKey for DatabaseKey {
     type Value = Arc<Database>;
 }
 
+struct BotConfigKey;
+impl serenity::prelude::TypeMapKey for BotConfigKey {
+    type Value = Arc<BotConfig>;
+}
+
 async fn handle_reaction_role(ctx: &Context, reaction: &Reaction, add: bool) {
     if reaction.user(&ctx.http).await.map_or(true, |u| u.bot) {
         return;
@@ -141,6 +308,7 @@ async fn handle_reaction_role(ctx: &Context, reaction: &Reaction, add: bool) {
 impl EventHandler for Handler {
     async fn ready(&self, _ctx: Context, ready: Ready) {
         println!("[INFO] Bot is connected as {} (ID: {})", ready.user.name, ready.user.id);
+        _ctx.data.write().await.insert::<BotUserIdKey>(ready.user.id);
 
         let patch_channel_id = ChannelId(1412130150325289203);
         let today_date = Utc::now().with_timezone(&Berlin).format("%Y-%m-%d").to_string();
@@ -165,10 +333,20 @@ impl EventHandler for Handler {
                 .create_application_command(|command| {
                     command.name("nuggies").description("Chat with Nuggies AI")
                         .create_option(|option| {
-                            option.name("message")
-                                .description("Your message to Nuggies")
-                                .kind(CommandOptionType::String)
-                                .required(true)
+                            option.name("chat")
+                                .description("Chat with Nuggies AI")
+                                .kind(CommandOptionType::SubCommand)
+                                .create_sub_option(|sub| {
+                                    sub.name("message")
+                                        .description("Your message to Nuggies")
+                                        .kind(CommandOptionType::String)
+                                        .required(true)
+                                })
+                        })
+                        .create_option(|option| {
+                            option.name("reset")
+                                .description("Make Nuggies forget the conversation in this channel")
+                                .kind(CommandOptionType::SubCommand)
                         })
                 })
                 .create_application_command(|command| {
@@ -190,6 +368,7 @@ impl EventHandler for Handler {
                                 .description("The language to translate to (e.g., 'French')")
                                 .kind(CommandOptionType::String)
                                 .required(true)
+                                .set_autocomplete(true)
                         })
                         .create_option(|option| {
                             option.name("text")
@@ -227,9 +406,193 @@ impl EventHandler for Handler {
                                 .required(true)
                         })
                 })
+                .create_application_command(|command| {
+                    command.name("persona").description("View or change Nuggies' personality in this server")
+                        .default_member_permissions(Permissions::MANAGE_GUILD)
+                        .dm_permission(false)
+                        .create_option(|option| {
+                            option.name("view")
+                                .description("Show the personality Nuggies uses in this server")
+                                .kind(CommandOptionType::SubCommand)
+                        })
+                        .create_option(|option| {
+                            option.name("set")
+                                .description("Give Nuggies a new personality (leave out the text to open an editor)")
+                                .kind(CommandOptionType::SubCommand)
+                                .create_sub_option(|sub| {
+                                    sub.name("text")
+                                        .description("The new personality")
+                                        .kind(CommandOptionType::String)
+                                        .required(false)
+                                        .max_length(persona::MAX_PERSONALITY_LENGTH as u16)
+                                })
+                        })
+                        .create_option(|option| {
+                            option.name("reset")
+                                .description("Go back to Nuggies' default personality")
+                                .kind(CommandOptionType::SubCommand)
+                        })
+                })
+                .create_application_command(|command| {
+                    command.name("translation").description("Translation settings for this server")
+                        .default_member_permissions(Permissions::MANAGE_GUILD)
+                        .dm_permission(false)
+                        .create_option(|option| {
+                            option.name("flags")
+                                .description("Translate messages when someone reacts with a country flag")
+                                .kind(CommandOptionType::SubCommand)
+                                .create_sub_option(|sub| {
+                                    sub.name("enabled")
+                                        .description("Whether flag reactions translate messages")
+                                        .kind(CommandOptionType::Boolean)
+                                        .required(true)
+                                })
+                        })
+                        .create_option(|option| {
+                            option.name("auto")
+                                .description("Translate every message in this channel")
+                                .kind(CommandOptionType::SubCommand)
+                                .create_sub_option(|sub| {
+                                    sub.name("languages")
+                                        .description("Languages to translate into, comma separated (e.g. 'English, German')")
+                                        .kind(CommandOptionType::String)
+                                        .required(true)
+                                        .max_length(200)
+                                })
+                                .create_sub_option(|sub| {
+                                    sub.name("webhook")
+                                        .description("Post translations under the author's name and avatar (needs Manage Webhooks)")
+                                        .kind(CommandOptionType::Boolean)
+                                        .required(false)
+                                })
+                        })
+                        .create_option(|option| {
+                            option.name("auto-off")
+                                .description("Stop translating messages in this channel")
+                                .kind(CommandOptionType::SubCommand)
+                        })
+                })
+                .create_application_command(|command| {
+                    command.name("channel").description("Nuggies' settings for this channel")
+                        .default_member_permissions(Permissions::MANAGE_GUILD)
+                        .dm_permission(false)
+                        .create_option(|option| {
+                            option.name("keyword")
+                                .description("Whether saying Nuggies' name here gets an answer (mentions always do)")
+                                .kind(CommandOptionType::SubCommand)
+                                .create_sub_option(|sub| {
+                                    sub.name("enabled")
+                                        .description("Whether Nuggies answers to her name in this channel")
+                                        .kind(CommandOptionType::Boolean)
+                                        .required(true)
+                                })
+                        })
+                        .create_option(|option| {
+                            option.name("ambient")
+                                .description("Let Nuggies chime in on conversations here without being asked")
+                                .kind(CommandOptionType::SubCommand)
+                                .create_sub_option(|sub| {
+                                    sub.name("enabled")
+                                        .description("Whether Nuggies chimes in on her own in this channel")
+                                        .kind(CommandOptionType::Boolean)
+                                        .required(true)
+                                })
+                                .create_sub_option(|sub| {
+                                    sub.name("chance")
+                                        .description("Chance in percent that a message makes her chime in (1-50). Defaults to 5.")
+                                        .kind(CommandOptionType::Integer)
+                                        .required(false)
+                                        .min_int_value(1)
+                                        .max_int_value(ambient::MAX_CHANCE_PERCENT)
+                                })
+                                .create_sub_option(|sub| {
+                                    sub.name("cooldown")
+                                        .description("Minutes she waits after chiming in (1-1440). Defaults to 30.")
+                                        .kind(CommandOptionType::Integer)
+                                        .required(false)
+                                        .min_int_value(1)
+                                        .max_int_value(ambient::MAX_COOLDOWN_MINUTES)
+                                })
+                        })
+                        .create_option(|option| {
+                            option.name("serious")
+                                .description("Mark this channel as serious, so Nuggies never chimes in on her own")
+                                .kind(CommandOptionType::SubCommand)
+                                .create_sub_option(|sub| {
+                                    sub.name("enabled")
+                                        .description("Whether this channel is serious")
+                                        .kind(CommandOptionType::Boolean)
+                                        .required(true)
+                                })
+                        })
+                })
+                .create_application_command(|command| {
+                    command.name("summarize").description("Catch up on what was said in this channel")
+                        .create_option(|option| {
+                            option.name("messages")
+                                .description("How many recent messages to summarize (10-500). Defaults to 100.")
+                                .kind(CommandOptionType::Integer)
+                                .required(false)
+                                .min_int_value(10)
+                                .max_int_value(summarize::MAX_MESSAGES)
+                        })
+                        .create_option(|option| {
+                            option.name("hours")
+                                .description("Summarize the last few hours instead (1-72)")
+                                .kind(CommandOptionType::Integer)
+                                .required(false)
+                                .min_int_value(1)
+                                .max_int_value(72)
+                        })
+                        .create_option(|option| {
+                            option.name("user")
+                                .description("Only summarize what this user said")
+                                .kind(CommandOptionType::User)
+                                .required(false)
+                        })
+                })
+                .create_application_command(|command| {
+                    command.name("remember").description("Teach Nuggies a fact about this server")
+                        .dm_permission(false)
+                        .create_option(|option| {
+                            option.name("fact")
+                                .description("What Nuggies should know, e.g. 'Movie night is every Friday at 8pm CET'")
+                                .kind(CommandOptionType::String)
+                                .required(true)
+                                .max_length(knowledge::MAX_FACT_LENGTH as u16)
+                        })
+                })
+                .create_application_command(|command| {
+                    command.name("forget").description("Make Nuggies forget a fact about this server")
+                        .dm_permission(false)
+                        .create_option(|option| {
+                            option.name("id")
+                                .description("The fact's number, see /facts")
+                                .kind(CommandOptionType::Integer)
+                                .required(true)
+                                .min_int_value(1)
+                        })
+                })
+                .create_application_command(|command| {
+                    command.name("facts").description("List what Nuggies knows about this server")
+                        .dm_permission(false)
+                        .create_option(|option| {
+                            option.name("page")
+                                .description("Which page to show")
+                                .kind(CommandOptionType::Integer)
+                                .required(false)
+                                .min_int_value(1)
+                        })
+                })
+                .create_application_command(|command| {
+                    command.name("usage").description("See how much AI you and this server have used")
+                })
                 .create_application_command(|command| {
                     command.name("help").description("Shows a list of all available commands")
                 })
+                .create_application_command(|command| {
+                    command.name(translate::CONTEXT_MENU).kind(CommandType::Message)
+                })
         })
             .await;
 
@@ -249,6 +612,13 @@ impl EventHandler for Handler {
             return;
         }
 
+        if msg.guild_id.is_some() {
+            let (ctx, msg) = (ctx.clone(), msg.clone());
+            tokio::spawn(async move {
+                autotranslate::handle_message(&ctx, &msg).await;
+            });
+        }
+
         let guild_id_opt = msg.guild_id;
 
         if msg.author.id.0 == 241614046913101825 && msg.content == "assignrole:gender" {
@@ -408,6 +778,17 @@ impl EventHandler for Handler {
         }
 
         let lower_content = msg.content.to_lowercase();
+        let bot_user_id = ctx.data.read().await.get::<BotUserIdKey>().copied();
+        let replying_to_bot = bot_user_id.is_some_and(|bot_id| reply_chain::is_reply_to_bot(&msg, bot_id));
+        let addressed = ctx.data.read().await.get::<BotConfigKey>().expect("Expected BotConfigKey in TypeMap.").trigger.addressed(&msg.content, bot_user_id);
+        let triggered = replying_to_bot || match addressed {
+            Some(trigger::Addressed::Mention) => true,
+            Some(trigger::Addressed::Name) => {
+                let db = ctx.data.read().await.get::<DatabaseKey>().expect("Expected DatabaseKey in TypeMap.").clone();
+                trigger::keyword_enabled(&db, msg.channel_id).await
+            }
+            None => false,
+        };
         if lower_content.contains("istanbul") {
             println!("[CMD] Triggered 'istanbul' response for user '{}' (ID: {}) in channel (ID: {})", msg.author.name, msg.author.id, msg.channel_id);
             let image_path = Path::new("constantinople.png");
@@ -416,36 +797,157 @@ impl EventHandler for Handler {
             } else {
                 let _ = msg.channel_id.say(&ctx.http, "That's Constantinople! (but I couldn't find the image)").await;
             }
-        } else if lower_content.contains("nuggies") {
+        } else if triggered {
             println!("[CMD] Triggered 'nuggies' AI response for user '{}' (ID: {}) in channel (ID: {})", msg.author.name, msg.author.id, msg.channel_id);
+            let cooldowns = ctx.data.read().await.get::<CooldownsKey>().expect("Expected CooldownsKey in TypeMap.").clone();
+            if let Err(wait) = cooldowns.check("message", msg.author.id.0, msg.channel_id.0, msg.guild_id.map(|id| id.0)) {
+                // Messages can't be ephemeral, so a reaction is the quietest way to say no.
+                println!("[COOLDOWN] User '{}' (ID: {}) is rate limited for another {:?}.", msg.author.name, msg.author.id, wait);
+                if let Err(e) = msg.react(&ctx.http, '⏳').await {
+                    eprintln!("[ERROR] Failed to react to message (ID: {}): {:?}", msg.id, e);
+                }
+                return;
+            }
             let typing = msg.channel_id.start_typing(&ctx.http);
-            let data = ctx.data.read().await;
-            let gemini_api_key = data.get::<GeminiApiKey>().expect("Expected GeminiApiKey in TypeMap.").clone();
-            let personality_prompt = get_nuggies_personality_prompt();
-            let modified_prompt = format!(
-                "{}\nRespond to the following message as Nuggies and keep the response at one or 2 sentences:\n\n{}",
-                personality_prompt, &msg.content
-            );
-            let response = call_gemini_api(&gemini_api_key, &modified_prompt).await.unwrap_or_else(|_| "My circuits are fried.".to_string());
+            // Clone what's needed and let go of the lock before any network calls, so `ready`
+            // can take the write lock.
+            let (llm, db, config, tenor, prompts, caller) = {
+                let data = ctx.data.read().await;
+                (
+                    data.get::<LlmProviderKey>().expect("Expected LlmProviderKey in TypeMap.").clone(),
+                    data.get::<DatabaseKey>().expect("Expected DatabaseKey in TypeMap.").clone(),
+                    data.get::<BotConfigKey>().expect("Expected BotConfigKey in TypeMap.").clone(),
+                    data.get::<TenorClient>().expect("Expected TenorClient in TypeMap.").clone(),
+                    data.get::<PromptsKey>().expect("Expected PromptsKey in TypeMap.").clone(),
+                    Caller::new(&data, msg.author.id, msg.guild_id, "message"),
+                )
+            };
+
+            let attachment_parts = match attachments::collect_inline_parts(&msg.attachments, &config).await {
+                Ok(parts) => parts,
+                Err(refusal) => {
+                    println!("[INFO] Refused attachments from user '{}' (ID: {}): {}", msg.author.name, msg.author.id, refusal);
+                    let _ = typing.map(|t| t.stop());
+                    let _ = msg.reply(&ctx.http, &refusal).await;
+                    return;
+                }
+            };
+
+            let history = history::load_history(&db, msg.channel_id.0, config.history_window).await;
+            let personality_prompt = persona::get_personality(&db, msg.guild_id).await;
+            let thread = match bot_user_id {
+                Some(bot_id) if replying_to_bot => {
+                    let chain = reply_chain::collect(&ctx, &msg, config.reply_chain_depth).await;
+                    println!("[ACTION] Including {} replied-to messages as context.", chain.len());
+                    format!("{}\n\n", reply_chain::render(&chain, bot_id))
+                }
+                _ => String::new(),
+            };
+            let facts = knowledge::relevant_facts(llm.as_ref(), &caller, &msg.content).await;
+            let modified_prompt = prompts.render("message", &[
+                ("facts", &knowledge::render(&facts)),
+                ("thread", &thread),
+                ("message", &safety::delimit_user_text(&format!("{}: {}", msg.author.name, trigger::name_bot_mentions(&msg.content, bot_user_id)))),
+            ]);
+            let tools = ToolContext { db: db.clone(), tenor, user_id: msg.author.id };
+            let response = match call_llm_with_parts(llm.as_ref(), &caller, Some(&personality_prompt), &history, &modified_prompt, attachment_parts, Some(&tools)).await {
+                Ok(response) => {
+                    let remembered = format!("{}{}", msg.content, attachments::history_note(&msg.attachments));
+                    history::append_exchange(&db, msg.channel_id.0, msg.author.id.0, &msg.author.name, &remembered, &response).await;
+                    response
+                }
+                Err(e) => llm_error_reply("message", &e),
+            };
             let _ = typing.map(|t| t.stop());
-            let _ = msg.channel_id.say(&ctx.http, &response).await;
+            reply::send_channel_reply(&ctx, msg.channel_id, &response, config.max_reply_chunks).await;
+        } else if msg.guild_id.is_some() {
+            ambient::handle_message(&ctx, &msg).await;
         }
     }
 
     async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
         handle_reaction_role(&ctx, &reaction, true).await;
+        flags::handle_reaction(&ctx, &reaction).await;
     }
 
     async fn reaction_remove(&self, ctx: Context, reaction: Reaction) {
         handle_reaction_role(&ctx, &reaction, false).await;
     }
 
+    // Moderation actions keep ambient chatter quiet for a while.
+    async fn guild_ban_addition(&self, ctx: Context, guild_id: GuildId, _banned_user: User) {
+        record_moderation(&ctx, guild_id).await;
+    }
+
+    async fn guild_member_update(&self, ctx: Context, update: GuildMemberUpdateEvent) {
+        if update.communication_disabled_until.is_some_and(|until| until.unix_timestamp() > Utc::now().timestamp()) {
+            record_moderation(&ctx, update.guild_id).await;
+        }
+    }
+
+    async fn message_delete_bulk(&self, ctx: Context, _channel_id: ChannelId, _deleted: Vec<MessageId>, guild_id: Option<GuildId>) {
+        if let Some(guild_id) = guild_id {
+            record_moderation(&ctx, guild_id).await;
+        }
+    }
+
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        match &interaction {
+            Interaction::ModalSubmit(modal) => {
+                if modal.data.custom_id == persona::MODAL_ID {
+                    persona::handle_modal_submit(&ctx, modal).await;
+                }
+                return;
+            }
+            Interaction::MessageComponent(component) => {
+                if component.data.custom_id.starts_with(funfact::BUTTON_PREFIX) {
+                    funfact::handle_button(&ctx, component).await;
+                }
+                return;
+            }
+            Interaction::Autocomplete(autocomplete) => {
+                if autocomplete.data.name == "translate" {
+                    translate::handle_autocomplete(&ctx, autocomplete).await;
+                }
+                return;
+            }
+            _ => {}
+        }
+
         if let Some(command) = interaction.application_command() {
             println!("[SLASH CMD] Received command: '/{}' from user '{}' (ID: {}) in Guild (ID: {:?}) Channel (ID: {:?}).", command.data.name, command.user.name, command.user.id, command.guild_id, command.channel_id);
 
+            let subcommand_name = command.data.options.first().map(|sub| sub.name.clone());
+            if command.data.name == "persona" && subcommand_name.as_deref() == Some("set")
+                && command.data.options[0].options.iter().all(|opt| opt.name != "text") {
+                persona::show_modal(&ctx, &command).await;
+                return;
+            }
+
+            // `/nuggies reset` doesn't call the AI, so it doesn't count against the cooldown.
+            let cooldown_name = match command.data.name.as_str() {
+                _ if subcommand_name.as_deref() == Some("reset") => "",
+                translate::CONTEXT_MENU => "translate",
+                name => name,
+            };
+            let cooldowns = ctx.data.read().await.get::<CooldownsKey>().expect("Expected CooldownsKey in TypeMap.").clone();
+            if let Err(wait) = cooldowns.check(cooldown_name, command.user.id.0, command.channel_id.0, command.guild_id.map(|id| id.0)) {
+                println!("[COOLDOWN] User '{}' (ID: {}) is rate limited on '/{}' for another {:?}.", command.user.name, command.user.id, command.data.name, wait);
+                if let Err(e) = command.create_interaction_response(&ctx.http, |response| {
+                    response.kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|data| data.content(cooldown::wait_message(wait)).ephemeral(true))
+                }).await {
+                    eprintln!("[ERROR] Could not send cooldown response: {:?}", e);
+                }
+                return;
+            }
+
+            // Persona, translation and channel settings, server facts, usage reports and translations from the context
+            // menu are only interesting to whoever asked.
+            let ephemeral = matches!(command.data.name.as_str(), "persona" | "translation" | "channel" | "remember" | "forget" | "facts" | "usage" | translate::CONTEXT_MENU);
             let _ = command.create_interaction_response(&ctx.http, |response| {
                 response.kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                    .interaction_response_data(|data| data.ephemeral(ephemeral))
             }).await;
 
             let user_id = command.user.id;
@@ -455,29 +957,72 @@ impl EventHandler for Handler {
             tokio::spawn(async move {
                 let response_content = match command_name.as_str() {
                     "nuggies" => {
-                        let message_option = command.data.options.iter().find(|opt| opt.name == "message");
-                        if let Some(message_text) = message_option.and_then(|opt| opt.value.as_ref().and_then(|v| v.as_str())) {
+                        let subcommand = command.data.options.first();
+                        let (db, llm, prompts, tenor, history_window, caller) = {
                             let data = ctx_clone.data.read().await;
-                            let gemini_api_key = data.get::<GeminiApiKey>().unwrap().clone();
-                            let personality_prompt = get_nuggies_personality_prompt();
-                            let prompt = format!(
-                                "{}\nRespond to the following message as Nuggies:\n\n{}",
-                                personality_prompt, message_text
-                            );
-                            match call_gemini_api(&gemini_api_key, &prompt).await {
-                                Ok(response) => format!("<@{}> asked: {}\n\n{}", user_id.0, message_text, response),
-                                Err(_) => "Sorry, I couldn't get a response from Nuggies right now.".to_string(),
+                            (
+                                data.get::<DatabaseKey>().unwrap().clone(),
+                                data.get::<LlmProviderKey>().unwrap().clone(),
+                                data.get::<PromptsKey>().unwrap().clone(),
+                                data.get::<TenorClient>().unwrap().clone(),
+                                data.get::<BotConfigKey>().unwrap().history_window,
+                                Caller::new(&data, user_id, command.guild_id, "nuggies"),
+                            )
+                        };
+                        let channel_id = command.channel_id;
+
+                        match subcommand.map(|sub| sub.name.as_str()) {
+                            Some("reset") => {
+                                match history::clear_history(&db, channel_id.0).await {
+                                    Ok(_) => {
+                                        println!("[ACTION] Cleared conversation history for channel (ID: {}).", channel_id);
+                                        "Done. I've forgotten everything we talked about in this channel.".to_string()
+                                    }
+                                    Err(e) => {
+                                        eprintln!("[ERROR] Failed to clear conversation history for channel (ID: {}): {:?}", channel_id, e);
+                                        "Sorry, I couldn't wipe my memory right now.".to_string()
+                                    }
+                                }
+                            }
+                            _ => {
+                                let message_option = subcommand.and_then(|sub| sub.options.iter().find(|opt| opt.name == "message"));
+                                if let Some(message_text) = message_option.and_then(|opt| opt.value.as_ref().and_then(|v| v.as_str())) {
+                                    let history = history::load_history(&db, channel_id.0, history_window).await;
+                                    let personality_prompt = persona::get_personality(&db, command.guild_id).await;
+                                    let facts = knowledge::relevant_facts(llm.as_ref(), &caller, message_text).await;
+                                    let prompt = prompts.render("nuggies", &[
+                                        ("facts", &knowledge::render(&facts)),
+                                        ("message", &safety::delimit_user_text(&format!("{}: {}", command.user.name, message_text))),
+                                    ]);
+                                    let tools = ToolContext { db: db.clone(), tenor, user_id };
+                                    match call_llm_with_parts(llm.as_ref(), &caller, Some(&personality_prompt), &history, &prompt, Vec::new(), Some(&tools)).await {
+                                        Ok(response) => {
+                                            history::append_exchange(&db, channel_id.0, user_id.0, &command.user.name, message_text, &response).await;
+                                            format!("<@{}> asked: {}\n\n{}", user_id.0, message_text, response)
+                                        }
+                                        Err(e) => llm_error_reply("nuggies", &e),
+                                    }
+                                } else { "Please provide a message for Nuggies.".to_string() }
                             }
-                        } else { "Please provide a message for Nuggies.".to_string() }
+                        }
                     },
                     "ask" => {
                         let question_option = command.data.options.iter().find(|opt| opt.name == "question");
                         if let Some(question_text) = question_option.and_then(|opt| opt.value.as_ref().and_then(|v| v.as_str())) {
-                            let data = ctx_clone.data.read().await;
-                            let gemini_api_key = data.get::<GeminiApiKey>().unwrap().clone();
-                            let prompt = format!("{}\n\nKeep your answer below 1800 characters.", question_text);
-                            let response = call_gemini_api(&gemini_api_key, &prompt).await.unwrap_or_else(|_| "Sorry, I couldn't get a response right now.".to_string());
-                            format!("<@{}> asked: {}\n\n{}", user_id.0, question_text, response)
+                            let (llm, prompts, caller) = {
+                                let data = ctx_clone.data.read().await;
+                                (
+                                    data.get::<LlmProviderKey>().unwrap().clone(),
+                                    data.get::<PromptsKey>().unwrap().clone(),
+                                    Caller::new(&data, user_id, command.guild_id, "ask"),
+                                )
+                            };
+                            let prefix = format!("<@{}> asked: {}\n\n", user_id.0, question_text);
+                            let prompt = prompts.render("ask", &[("question", question_text)]);
+                            let response = stream_llm_to_interaction(&ctx_clone, &command, &prefix, llm.as_ref(), &caller, None, &prompt)
+                                .await
+                                .unwrap_or_else(|e| llm_error_reply("ask", &e));
+                            format!("{}{}", prefix, response)
                         } else { "Please provide a question.".to_string() }
                     },
                     "translate" => {
@@ -485,20 +1030,50 @@ impl EventHandler for Handler {
                         let text_opt = command.data.options.iter().find(|o| o.name == "text").and_then(|o| o.value.as_ref().and_then(|v| v.as_str()));
 
                         if let (Some(language), Some(text)) = (lang_opt, text_opt) {
-                            let data = ctx_clone.data.read().await;
-                            let gemini_api_key = data.get::<GeminiApiKey>().unwrap().clone();
-                            let prompt = format!("Translate the following text to {} exactly and only output the translated text:\n\n{}", language, text);
-                            call_gemini_api(&gemini_api_key, &prompt).await.unwrap_or_else(|_| "Sorry, I couldn't translate that.".to_string())
+                            let (llm, prompts, caller) = {
+                                let data = ctx_clone.data.read().await;
+                                (
+                                    data.get::<LlmProviderKey>().unwrap().clone(),
+                                    data.get::<PromptsKey>().unwrap().clone(),
+                                    Caller::new(&data, user_id, command.guild_id, "translate"),
+                                )
+                            };
+                            match translate::translate(llm.as_ref(), &caller, &prompts, text, language).await {
+                                Ok(translation) => translate::render(&translation, language),
+                                Err(e) => llm_error_reply("translate", &e),
+                            }
                         } else { "Please provide both a language and text.".to_string() }
                     },
+                    translate::CONTEXT_MENU => {
+                        match command.data.target() {
+                            Some(ResolvedTarget::Message(message)) if !message.content.trim().is_empty() => {
+                                let language = translate::language_for_locale(&command.locale);
+                                let (llm, prompts, caller) = {
+                                    let data = ctx_clone.data.read().await;
+                                    (
+                                        data.get::<LlmProviderKey>().unwrap().clone(),
+                                        data.get::<PromptsKey>().unwrap().clone(),
+                                        Caller::new(&data, user_id, command.guild_id, "translate"),
+                                    )
+                                };
+                                match translate::translate(llm.as_ref(), &caller, &prompts, &message.content, language).await {
+                                    Ok(translation) => translate::render(&translation, language),
+                                    Err(e) => llm_error_reply("translate", &e),
+                                }
+                            }
+                            _ => "That message has no text I could translate.".to_string(),
+                        }
+                    },
                     "fox" => {
-                        let data = ctx_clone.data.read().await;
-                        let tenor_api_key = data.get::<TenorApiKey>().unwrap().clone();
-                        get_random_fox_gif(&tenor_api_key).await.unwrap_or_else(|_| "https://media.tenor.com/YxT1w3VX5BAAAAAM/fox-dance.gif".to_string())
+                        let tenor = ctx_clone.data.read().await.get::<TenorClient>().unwrap().clone();
+                        match get_random_fox_gif(&tenor).await {
+                            Ok(gif) => gif,
+                            Err(SendError::CircuitOpen { .. }) => format!("The foxes are hiding from me right now (Tenor seems to be down). Have my favourite one instead:\n{}", FALLBACK_FOX_GIF),
+                            Err(SendError::Request(_)) => FALLBACK_FOX_GIF.to_string(),
+                        }
                     },
                     "daily" => {
-                        let data = ctx_clone.data.read().await;
-                        let db = data.get::<DatabaseKey>().unwrap();
+                        let db = ctx_clone.data.read().await.get::<DatabaseKey>().unwrap().clone();
                         let conn = db.pool.get().await.expect("Failed to get DB connection");
                         let user_id_i64 = *user_id.as_u64() as i64;
                         let today = Utc::now().with_timezone(&Berlin).date_naive();
@@ -527,8 +1102,7 @@ impl EventHandler for Handler {
                         }
                     },
                     "nuggetbox" => {
-                        let data = ctx_clone.data.read().await;
-                        let db = data.get::<DatabaseKey>().unwrap();
+                        let db = ctx_clone.data.read().await.get::<DatabaseKey>().unwrap().clone();
                         let conn = db.pool.get().await.expect("Failed to get DB connection");
                         let user_id_i64 = *user_id.as_u64() as i64;
 
@@ -540,8 +1114,7 @@ impl EventHandler for Handler {
                         }
                     },
                     "leaderboard" => {
-                        let data = ctx_clone.data.read().await;
-                        let db = data.get::<DatabaseKey>().unwrap();
+                        let db = ctx_clone.data.read().await.get::<DatabaseKey>().unwrap().clone();
                         let conn = db.pool.get().await.expect("Failed to get DB connection");
 
                         match conn.query("SELECT user_id, nuggets FROM users ORDER BY nuggets DESC LIMIT 10", &[]).await {
@@ -572,10 +1145,25 @@ impl EventHandler for Handler {
                         }
                     },
                     "slots" => {
-                        let data = ctx_clone.data.read().await;
-                        let db = data.get::<DatabaseKey>().unwrap();
-                        let conn = db.pool.get().await.expect("Failed to get DB connection");
-                        let gemini_api_key = data.get::<GeminiApiKey>().unwrap().clone();
+                        // The quip can take a while; don't keep the TypeMap locked for it.
+                        let (db, llm, prompts, max_reply_chunks, caller) = {
+                            let data = ctx_clone.data.read().await;
+                            (
+                                data.get::<DatabaseKey>().unwrap().clone(),
+                                data.get::<LlmProviderKey>().unwrap().clone(),
+                                data.get::<PromptsKey>().unwrap().clone(),
+                                data.get::<BotConfigKey>().unwrap().max_reply_chunks,
+                                Caller::new(&data, user_id, command.guild_id, "slots"),
+                            )
+                        };
+                        let conn = match db.pool.get().await {
+                            Ok(conn) => conn,
+                            Err(e) => {
+                                eprintln!("[ERROR] Failed to get DB connection for slots: {:?}", e);
+                                reply::edit_interaction_reply(&ctx_clone, &command, "Sorry, the slot machine is out of order right now.", max_reply_chunks).await;
+                                return;
+                            }
+                        };
                         let user_id_i64 = *user_id.as_u64() as i64;
                         
                         let bet_amount = command.data.options.iter()
@@ -608,10 +1196,7 @@ impl EventHandler for Handler {
                                         let chosen_symbol = *weighted_list.choose(&mut rng).unwrap();
                                         let (_, jackpot_multiplier, _) = symbols.iter().find(|(sym, _, _)| *sym == chosen_symbol).unwrap();
                                         let jackpot_win = bet_amount * jackpot_multiplier;
-                                        let prompt = format!(
-                                            "{}\nAs Nuggies, write a witty and sarcastic short one-liner for a user who just won {} nuggets(the bet currency) at a slot machine.",
-                                            get_nuggies_personality_prompt(), jackpot_win
-                                        );
+                                        let prompt = prompts.render("slots_win", &[("winnings", &jackpot_win.to_string())]);
                                         (chosen_symbol, chosen_symbol, chosen_symbol, jackpot_win, prompt)
                                 
                                     } else if outcome_roll <= 30 {
@@ -621,10 +1206,7 @@ impl EventHandler for Handler {
                                         let symbol_b = *chosen.next().unwrap();
                                         let mut result = [symbol_a, symbol_a, symbol_b];
                                         result.shuffle(&mut rng);
-                                        let prompt = format!(
-                                            "{}\nAs Nuggies, write a witty and sarcastic short one-liner for a user who just broke even at a slot machine, getting their {} nuggets(the bet currency) back.",
-                                            get_nuggies_personality_prompt(), bet_amount
-                                        );
+                                        let prompt = prompts.render("slots_even", &[("bet", &bet_amount.to_string())]);
                                         (result[0], result[1], result[2], bet_amount, prompt)
                                     } else {
                                         let all_symbols: Vec<&str> = symbols.iter().map(|(s, _, _)| *s).collect();
@@ -632,10 +1214,7 @@ impl EventHandler for Handler {
                                         let s1 = *chosen.next().unwrap();
                                         let s2 = *chosen.next().unwrap();
                                         let s3 = *chosen.next().unwrap();
-                                        let prompt = format!(
-                                            "{}\nAs Nuggies, write a witty and sarcastic short one-liner for a user who just lost their {} nuggets(the bet currency) at a slot machine. They were eaten by a Fox",
-                                            get_nuggies_personality_prompt(), bet_amount
-                                        );
+                                        let prompt = prompts.render("slots_loss", &[("bet", &bet_amount.to_string())]);
                                         (s1, s2, s3, 0, prompt)
                                     }
                                 };
@@ -643,19 +1222,52 @@ impl EventHandler for Handler {
                                 let display = format!("[ {} | {} | {} ]", s1, s2, s3);
                                 let new_total = nuggets - bet_amount + winnings;
                                 let params: &[&(dyn ToSql + Sync)] = &[&new_total, &user_id_i64];
-                                conn.execute("UPDATE users SET nuggets = $1 WHERE user_id = $2", params).await.unwrap();
-
-                                let witty_response = call_gemini_api(&gemini_api_key, &response_prompt)
-                                    .await
-                                    .unwrap_or_else(|_| "...".to_string());
+                                if let Err(e) = conn.execute("UPDATE users SET nuggets = $1 WHERE user_id = $2", params).await {
+                                    eprintln!("[ERROR] Failed to update nuggets for User (ID: {}) after slots: {:?}", user_id, e);
+                                    reply::edit_interaction_reply(&ctx_clone, &command, "Sorry, the slot machine jammed. Your nuggets are untouched.", max_reply_chunks).await;
+                                    return;
+                                }
+                                // The quip below can take a while; give the connection back to the pool first.
+                                drop(conn);
 
-                                if winnings > bet_amount {
-                                    format!("{}\n\nYou won {} nuggets!\n{}", display, winnings, witty_response)
+                                let (outcome, result) = if winnings > bet_amount {
+                                    (slots::Outcome::Win, format!("{}\n\nYou won {} nuggets!", display, winnings))
                                 } else if winnings == bet_amount {
-                                    format!("{}\n\nYou get your {} nuggets back.\n{}", display, bet_amount, witty_response)
+                                    (slots::Outcome::BreakEven, format!("{}\n\nYou get your {} nuggets back.", display, bet_amount))
                                 } else {
-                                    format!("{}\n\n{}", display, witty_response)
+                                    (slots::Outcome::Loss, format!("{}\n", display))
+                                };
+
+                                // Show the reels right away; the quip is edited in once it's there.
+                                if let Err(e) = command.edit_original_interaction_response(&ctx_clone.http, |response| {
+                                    response.content(format!("{}\n*…*", result)).allowed_mentions(|mentions| mentions.empty_parse())
+                                }).await {
+                                    eprintln!("[ERROR] Could not edit interaction response: {:?}", e);
                                 }
+
+                                let personality_prompt = persona::get_personality(&db, command.guild_id).await;
+                                // The call runs in its own task, so a slow answer isn't cancelled (and still
+                                // counts towards usage); it just arrives after the local quip.
+                                let quip_llm = llm.clone();
+                                let mut quip_call = tokio::spawn(async move {
+                                    call_llm(quip_llm.as_ref(), &caller, Some(&personality_prompt), &[], &response_prompt).await
+                                });
+                                let quip = match tokio::time::timeout(slots::QUIP_TIMEOUT, &mut quip_call).await {
+                                    Ok(Ok(Ok(quip))) => quip,
+                                    Ok(_) => slots::fallback_quip(outcome).to_string(),
+                                    Err(_) => {
+                                        eprintln!("[WARN] Slots quip took longer than {:?}, showing a local one until it arrives.", slots::QUIP_TIMEOUT);
+                                        let fallback = format!("{}\n{}", result, slots::fallback_quip(outcome));
+                                        reply::edit_interaction_reply(&ctx_clone, &command, &fallback, max_reply_chunks).await;
+                                        tokio::spawn(async move {
+                                            if let Ok(Ok(quip)) = quip_call.await {
+                                                reply::edit_interaction_reply(&ctx_clone, &command, &format!("{}\n{}", result, quip), max_reply_chunks).await;
+                                            }
+                                        });
+                                        return;
+                                    }
+                                };
+                                format!("{}\n{}", result, quip)
                             }
                         } else {
                             "You don't have a nuggetbox yet! Use `/daily` to get your first nuggets.".to_string()
@@ -667,51 +1279,217 @@ impl EventHandler for Handler {
                             .and_then(|opt| opt.value.as_ref())
                             .and_then(|v| v.as_str())
                             .unwrap_or("random");
+                        let topic = funfact::normalize_topic(topic_option);
 
-                        let data = ctx_clone.data.read().await;
-                        let gemini_api_key = data.get::<GeminiApiKey>().unwrap().clone();
-                        let personality_prompt = get_nuggies_personality_prompt();
-
-                        let funfact_prompt = if topic_option.to_lowercase() == "random" {
-                            format!(
-                                "{}\n\nState a single random semi-interesting to very interesting fun fact with a maximum of 1800 symbols. \
-                                The topic can be from alternative subculture and music, history before 1800 (like ancient Rome, Vikings, the Byzantine Empire, the Ottoman Empire, feudal Japan, or medieval Europe), \
-                                geography, linguistics (primarily Indo-European languages, but Japanese, Chinese, or Korean are also great), physics, or even contemporary subjects. \
-                                Feel free to choose any topic, but just stick to one fact per response.",
-                                personality_prompt
+                        let (llm, db, prompts, caller) = {
+                            let data = ctx_clone.data.read().await;
+                            (
+                                data.get::<LlmProviderKey>().unwrap().clone(),
+                                data.get::<DatabaseKey>().unwrap().clone(),
+                                data.get::<PromptsKey>().unwrap().clone(),
+                                Caller::new(&data, user_id, command.guild_id, "funfact"),
                             )
-                        } else {
-                            format!(
-                                "{}\n\nState a single, semi-interesting to very interesting fun fact about {}. Keep the fact concise and under 1800 characters.",
-                                personality_prompt, topic_option
+                        };
+                        let personality_prompt = persona::get_personality(&db, command.guild_id).await;
+
+                        match funfact::generate(llm.as_ref(), &caller, &prompts, &personality_prompt, &topic).await {
+                            Ok(fact) => {
+                                if let Err(e) = command.edit_original_interaction_response(&ctx_clone.http, |response| funfact::fill_response(response, &fact, &topic)).await {
+                                    eprintln!("[ERROR] Could not edit interaction response: {:?}", e);
+                                }
+                                return;
+                            }
+                            Err(e) => llm_error_reply("funfact", &e),
+                        }
+                    },
+                    "persona" => {
+                        let db = ctx_clone.data.read().await.get::<DatabaseKey>().unwrap().clone();
+
+                        match command.guild_id {
+                            None => "Personalities can only be changed in a server.".to_string(),
+                            Some(_) if !persona::can_manage(command.member.as_ref()) => "You need the Manage Server permission to change my personality.".to_string(),
+                            Some(guild_id) => match subcommand_name.as_deref() {
+                                Some("set") => {
+                                    let text = command.data.options[0].options.iter()
+                                        .find(|opt| opt.name == "text")
+                                        .and_then(|opt| opt.value.as_ref())
+                                        .and_then(|v| v.as_str())
+                                        .unwrap_or_default();
+                                    persona::save_personality(&db, guild_id, text, user_id.0).await
+                                }
+                                Some("reset") => match persona::reset_personality(&db, guild_id).await {
+                                    Ok(_) => {
+                                        println!("[ACTION] Reset personality for Guild (ID: {}).", guild_id);
+                                        "Done. I'm back to my old self.".to_string()
+                                    }
+                                    Err(e) => {
+                                        eprintln!("[ERROR] Failed to reset personality for Guild (ID: {}): {:?}", guild_id, e);
+                                        "Sorry, I couldn't reset my personality right now.".to_string()
+                                    }
+                                },
+                                _ => match persona::get_custom_personality(&db, guild_id).await {
+                                    Some(personality) => format!("**My personality in this server:**\n```\n{}\n```", personality),
+                                    None => format!("**I'm using my default personality:**\n```\n{}\n```", persona::DEFAULT_PERSONALITY),
+                                },
+                            },
+                        }
+                    },
+                    "translation" => {
+                        let db = ctx_clone.data.read().await.get::<DatabaseKey>().unwrap().clone();
+                        let option = |name: &str| command.data.options.first()
+                            .and_then(|sub| sub.options.iter().find(|opt| opt.name == name))
+                            .and_then(|opt| opt.value.as_ref());
+
+                        match command.guild_id {
+                            None => "Translation settings can only be changed in a server.".to_string(),
+                            Some(_) if !persona::can_manage(command.member.as_ref()) => "You need the Manage Server permission to change translation settings.".to_string(),
+                            Some(guild_id) => match subcommand_name.as_deref() {
+                                Some("auto") => {
+                                    let languages = option("languages").and_then(|v| v.as_str()).unwrap_or_default();
+                                    let webhook = option("webhook").and_then(|v| v.as_bool()).unwrap_or(false);
+                                    autotranslate::enable(&ctx_clone, &db, guild_id, command.channel_id, languages, webhook, user_id.0).await
+                                }
+                                Some("auto-off") => autotranslate::disable(&ctx_clone, &db, command.channel_id).await,
+                                _ => {
+                                    let enabled = option("enabled").and_then(|v| v.as_bool()).unwrap_or(false);
+                                    flags::save_setting(&db, guild_id, enabled, user_id.0).await
+                                }
+                            },
+                        }
+                    },
+                    "channel" => {
+                        let db = ctx_clone.data.read().await.get::<DatabaseKey>().unwrap().clone();
+                        let option = |name: &str| command.data.options.first()
+                            .and_then(|sub| sub.options.iter().find(|opt| opt.name == name))
+                            .and_then(|opt| opt.value.as_ref());
+
+                        match command.guild_id {
+                            None => "Channel settings can only be changed in a server.".to_string(),
+                            Some(_) if !persona::can_manage(command.member.as_ref()) => "You need the Manage Server permission to change channel settings.".to_string(),
+                            Some(guild_id) => {
+                                let enabled = option("enabled").and_then(|v| v.as_bool()).unwrap_or(true);
+                                match subcommand_name.as_deref() {
+                                    Some("ambient") if enabled => {
+                                        let chance = option("chance").and_then(|v| v.as_i64()).unwrap_or(ambient::DEFAULT_CHANCE_PERCENT);
+                                        let cooldown = option("cooldown").and_then(|v| v.as_i64()).unwrap_or(ambient::DEFAULT_COOLDOWN_MINUTES);
+                                        ambient::enable(&db, guild_id, command.channel_id, chance, cooldown, user_id.0).await
+                                    }
+                                    Some("ambient") => ambient::disable(&db, command.channel_id).await,
+                                    Some("serious") => ambient::set_serious(&db, guild_id, command.channel_id, enabled, user_id.0).await,
+                                    _ => trigger::save_keyword_setting(&db, guild_id, command.channel_id, enabled, user_id.0).await,
+                                }
+                            }
+                        }
+                    },
+                    "summarize" => {
+                        let option = |name: &str| command.data.options.iter().find(|opt| opt.name == name).and_then(|opt| opt.value.as_ref());
+                        let hours = option("hours").and_then(|v| v.as_i64());
+                        let selection = summarize::Selection {
+                            limit: option("messages").and_then(|v| v.as_u64()).unwrap_or(summarize::DEFAULT_MESSAGES),
+                            since: hours.map(|h| Utc::now() - chrono::Duration::hours(h)),
+                            user: option("user").and_then(|v| v.as_str()).and_then(|id| id.parse().ok()).map(UserId),
+                        };
+
+                        // Fetching the history and the map-reduce calls can take a while, so nothing here holds the lock.
+                        let (llm, db, prompts, bot_id, caller) = {
+                            let data = ctx_clone.data.read().await;
+                            (
+                                data.get::<LlmProviderKey>().unwrap().clone(),
+                                data.get::<DatabaseKey>().unwrap().clone(),
+                                data.get::<PromptsKey>().unwrap().clone(),
+                                data.get::<BotUserIdKey>().copied(),
+                                Caller::new(&data, user_id, command.guild_id, "summarize"),
                             )
                         };
 
-                        call_gemini_api(&gemini_api_key, &funfact_prompt)
-                            .await
-                            .unwrap_or_else(|_| "My fact-generating circuits seem to be on the fritz. Ask later.".to_string())
+                        match summarize::fetch_messages(&ctx_clone, command.channel_id, &selection).await {
+                            Err(e) => {
+                                eprintln!("[ERROR] Failed to fetch messages in channel (ID: {}): {:?}", command.channel_id, e);
+                                "Sorry, I couldn't read this channel's history. Do I have permission to?".to_string()
+                            }
+                            Ok(messages) => {
+                                let lines = summarize::build_transcript(&ctx_clone, command.guild_id, &messages, bot_id).await;
+                                if lines.is_empty() {
+                                    "There's nothing to summarize. It's been quiet.".to_string()
+                                } else {
+                                    let personality_prompt = persona::get_personality(&db, command.guild_id).await;
+                                    let scope = match (hours, selection.user) {
+                                        (Some(h), Some(user)) => format!("what <@{}> said in the last {} hours", user.0, h),
+                                        (Some(h), None) => format!("the last {} hours", h),
+                                        (None, Some(user)) => format!("what <@{}> said in the last {} messages", user.0, selection.limit),
+                                        (None, None) => format!("the last {} messages", messages.len()),
+                                    };
+                                    match summarize::summarize(llm.as_ref(), &caller, &prompts, &personality_prompt, &lines).await {
+                                        Ok(summary) => format!("📜 **Summary of {}**\n\n{}", scope, summary),
+                                        Err(e) => llm_error_reply("summarize", &e),
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    "remember" | "forget" | "facts" => {
+                        let (db, llm, caller) = {
+                            let data = ctx_clone.data.read().await;
+                            (
+                                data.get::<DatabaseKey>().unwrap().clone(),
+                                data.get::<LlmProviderKey>().unwrap().clone(),
+                                Caller::new(&data, user_id, command.guild_id, "remember"),
+                            )
+                        };
+                        let option = |name: &str| command.data.options.iter().find(|opt| opt.name == name).and_then(|opt| opt.value.as_ref());
+
+                        match command.guild_id {
+                            None => "I only remember facts about servers.".to_string(),
+                            Some(guild_id) => match command_name.as_str() {
+                                "remember" => {
+                                    let fact = option("fact").and_then(|v| v.as_str()).unwrap_or_default();
+                                    knowledge::remember(llm.as_ref(), &caller, guild_id, fact).await
+                                }
+                                "forget" => {
+                                    let id = option("id").and_then(|v| v.as_i64()).unwrap_or_default();
+                                    knowledge::forget(&db, guild_id, id, user_id, persona::can_manage(command.member.as_ref())).await
+                                }
+                                _ => knowledge::list(&db, guild_id, option("page").and_then(|v| v.as_i64()).unwrap_or(1)).await,
+                            },
+                        }
+                    },
+                    "usage" => {
+                        let (db, config) = {
+                            let data = ctx_clone.data.read().await;
+                            (data.get::<DatabaseKey>().unwrap().clone(), data.get::<BotConfigKey>().unwrap().clone())
+                        };
+                        usage::summary(&db, &config, user_id, command.guild_id).await
                     },
                     "help" => {
                         "Here's a list of my commands:\n\n\
-                        **/nuggies `[message]`**: Chat with Nuggies AI.\n\
+                        **/nuggies chat `[message]`**: Chat with Nuggies AI. She remembers the conversation in each channel.\n\
+                        **/nuggies reset**: Make Nuggies forget the conversation in this channel.\n\
                         **/ask `[question]`**: Ask the AI a question.\n\
                         **/fox**: Get a random fox GIF.\n\
-                        **/translate `[language]` `[text]`**: Translate text to a specified language.\n\
+                        **/translate `[language]` `[text]`**: Translate text to a specified language. Right-click a message and pick *Apps → Translate to my language* to translate it into your Discord language.\n\
                         **/daily**: Claim your daily nuggets.\n\
                         **/nuggetbox**: Check your personal amount of nuggets.\n\
                         **/leaderboard**: Shows the top nugget holders.\n\
                         **/slots `[amount]`**: Spend nuggets for a chance to win big! (1-10, defaults to 5).\n\
-                        **/funfact `[topic]`**: Get an interesting fun fact about a specific topic (use 'random' for a random topic).\n\
+                        **/funfact `[topic]`**: Get an interesting fun fact about a specific topic (use 'random' for a random topic). Press \"Another one\" for more.\n\
+                        **/summarize `[messages]` `[hours]` `[user]`**: Catch up on what was said in this channel.\n\
+                        **/remember `[fact]`**: Teach me something about this server, like an in-joke, an event time or a rule.\n\
+                        **/facts `[page]`**: List what I know about this server.\n\
+                        **/forget `[id]`**: Make me forget one of your facts (any fact with Manage Server).\n\
+                        **/usage**: See how much AI you and this server have used.\n\
+                        **/persona `view|set|reset`**: View or change my personality in this server (requires Manage Server).\n\
+                        **/translation flags `[enabled]`**: Let country flag reactions translate messages in this server (requires Manage Server).\n\
+                        **/translation auto `[languages]` `[webhook]`**: Translate every message in this channel into the given languages; `/translation auto-off` stops it (requires Manage Server).\n\
+                        **/channel keyword `[enabled]`**: Turn off answering to my name in this channel; mentions and replies still work (requires Manage Server).\n\
+                        **/channel ambient `[enabled]` `[chance]` `[cooldown]`**: Let me chime in on conversations in this channel now and then (requires Manage Server).\n\
+                        **/channel serious `[enabled]`**: Mark this channel as serious, so I never chime in on my own (requires Manage Server).\n\
                         **/help**: Shows this help message.".to_string()
                     },
                     _ => "Unknown command.".to_string(),
                 };
 
-                if let Err(e) = command.edit_original_interaction_response(&ctx_clone.http, |response| {
-                    response.content(response_content)
-                }).await {
-                    eprintln!("[ERROR] Could not edit interaction response: {:?}", e);
-                }
+                let max_reply_chunks = ctx_clone.data.read().await.get::<BotConfigKey>().unwrap().max_reply_chunks;
+                reply::edit_interaction_reply(&ctx_clone, &command, &response_content, max_reply_chunks).await;
             });
         }
     }
@@ -744,18 +1522,55 @@ async fn get_or_create_role(ctx: &Context, guild_id: GuildId, role_name: &str) -
     }
 }
 
-fn get_nuggies_personality_prompt() -> &'static str {
-    "You are an Female AI assistant called 'Nuggies'.\
-     You have a somewhat friendly, slightly norse nordic, slightly pagan, sarcastic, quite gothic (NOT EDGY) and somewhat unhinged personality.\
-     dont Roleplay"
+/// What to tell the user when the AI call behind `command` fails.
+fn llm_error_reply(command: &str, error: &LlmError) -> String {
+    let prefix = match command {
+        "nuggies" => "Sorry, I couldn't get a response from Nuggies right now.",
+        "ask" => "Sorry, I couldn't get a response right now.",
+        "translate" => "Sorry, I couldn't translate that.",
+        "funfact" => "My fact-generating circuits seem to be on the fritz.",
+        "summarize" => "Sorry, I couldn't summarize that.",
+        _ => "My circuits are fried.",
+    };
+    format!("{} {}", prefix, error.user_reason())
 }
 
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
     let discord_token = env::var("DISCORD_TOKEN").expect("Expected DISCORD_TOKEN in the environment");
-    let gemini_api_key = env::var("GEMINI_API_KEY").expect("Expected GEMINI_API_KEY in the environment");
     let tenor_api_key = env::var("TENOR_API_KEY").expect("Expected TENOR_API_KEY in the environment");
+    let bot_config = BotConfig::from_env();
+
+    let http_client = upstream::build_http_client(Duration::from_secs(bot_config.upstream_timeout_secs));
+    let make_upstream = |name: &'static str, api_key: String| Upstream {
+        name,
+        http: http_client.clone(),
+        api_key,
+        retry: RetryPolicy {
+            max_retries: bot_config.upstream_max_retries,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+        },
+        breaker: CircuitBreaker::new(bot_config.breaker_failure_threshold, Duration::from_secs(bot_config.breaker_cooldown_secs)),
+    };
+    let tenor = make_upstream("Tenor", tenor_api_key);
+    let llm: Arc<dyn LlmProvider> = match bot_config.llm_provider.as_str() {
+        "openai" => {
+            let api_key = env::var("OPENAI_API_KEY").unwrap_or_default();
+            Arc::new(OpenAiProvider::new(make_upstream("OpenAI", api_key), bot_config.openai_base_url.clone(), bot_config.openai_model.clone(), bot_config.embedding_model.clone()))
+        }
+        "mock" => Arc::new(MockProvider),
+        _ => {
+            let gemini_api_key = env::var("GEMINI_API_KEY").expect("Expected GEMINI_API_KEY in the environment");
+            Arc::new(GeminiProvider::new(make_upstream("Gemini", gemini_api_key), bot_config.gemini_model.clone(), bot_config.embedding_model.clone()))
+        }
+    };
+    let llm: Arc<dyn LlmProvider> = match bot_config.fallback_model.clone() {
+        Some(model) if bot_config.llm_provider != "mock" => Arc::new(FallbackProvider::new(llm, model)),
+        _ => llm,
+    };
+    println!("[INFO] Using LLM provider '{}'.", llm.name());
 
     let intents = GatewayIntents::non_privileged()
         | GatewayIntents::MESSAGE_CONTENT
@@ -770,9 +1585,15 @@ async fn main() {
 
     {
         let mut data = client.data.write().await;
-        data.insert::<GeminiApiKey>(Arc::new(gemini_api_key));
-        data.insert::<TenorApiKey>(Arc::new(tenor_api_key));
+        data.insert::<LlmProviderKey>(llm);
+        data.insert::<TenorClient>(Arc::new(tenor));
         data.insert::<DatabaseKey>(Arc::new(Database::new().await));
+        data.insert::<BotConfigKey>(Arc::new(bot_config));
+        data.insert::<CooldownsKey>(Arc::new(Cooldowns::from_env()));
+        data.insert::<AmbientKey>(Arc::new(ambient::AmbientState::default()));
+        let prompts = Arc::new(Prompts::from_env());
+        prompts.clone().watch();
+        data.insert::<PromptsKey>(prompts);
     }
 
     if let Err(why) = client.start().await {
@@ -780,61 +1601,167 @@ async fn main() {
     }
 }
 
-struct GeminiApiKey;
-impl serenity::prelude::TypeMapKey for GeminiApiKey {
-    type Value = Arc<String>;
+struct LlmProviderKey;
+impl serenity::prelude::TypeMapKey for LlmProviderKey {
+    type Value = Arc<dyn LlmProvider>;
+}
+
+struct TenorClient;
+impl serenity::prelude::TypeMapKey for TenorClient {
+    type Value = Arc<Upstream>;
 }
 
-struct TenorApiKey;
-impl serenity::prelude::TypeMapKey for TenorApiKey {
-    type Value = Arc<String>;
+/// Nuggies' own user ID, known once the bot is ready.
+struct BotUserIdKey;
+impl serenity::prelude::TypeMapKey for BotUserIdKey {
+    type Value = UserId;
 }
 
-async fn call_gemini_api(api_key: &str, message: &str) -> Result<String, reqwest::Error> {
-    let client = HttpClient::new();
-    let url = "https://generativelanguage.googleapis.com/v1beta/models/gemini-2.5-flash:generateContent";
-    let request_body = serde_json::json!({ "contents": [{ "parts": [{ "text": message }] }] });
+struct AmbientKey;
+impl serenity::prelude::TypeMapKey for AmbientKey {
+    type Value = Arc<ambient::AmbientState>;
+}
 
-    println!("[API REQUEST - Gemini] Sending request for message: \"{}\"", message);
+struct CooldownsKey;
+impl serenity::prelude::TypeMapKey for CooldownsKey {
+    type Value = Arc<Cooldowns>;
+}
 
-    let response = client.post(url)
-        .header("x-goog-api-key", api_key)
-        .json(&request_body)
-        .send()
-        .await?;
+struct PromptsKey;
+impl serenity::prelude::TypeMapKey for PromptsKey {
+    type Value = Arc<Prompts>;
+}
 
-    let response_json = response.json::<serde_json::Value>().await?;
+const STREAM_EDIT_INTERVAL: Duration = Duration::from_millis(1500);
+
+const FALLBACK_FOX_GIF: &str = "https://media.tenor.com/YxT1w3VX5BAAAAAM/fox-dance.gif";
+
+/// Builds a request with `message` as the newest user turn, preceded by the replayed `history`,
+/// using the generation settings of the caller's command.
+/// The persona (if any) goes into the system instruction rather than the user text, so users
+/// can't simply talk Nuggies out of it.
+fn build_llm_request(caller: &Caller, system_instruction: Option<&str>, history: &[HistoryTurn], message: ChatMessage) -> LlmRequest {
+    let mut messages: Vec<ChatMessage> = history.iter()
+        .map(|turn| if turn.role == "model" { ChatMessage::model(turn.as_prompt_text()) } else { ChatMessage::user(turn.as_prompt_text()) })
+        .collect();
+    messages.push(message);
+    LlmRequest {
+        system_instruction: system_instruction.map(|s| s.to_string()),
+        messages,
+        settings: caller.config.generation_settings(caller.command),
+        ..Default::default()
+    }
+}
 
-    let response_string = serde_json::to_string(&response_json).unwrap_or_else(|_| "{}".to_string());
-    let truncated_response = response_string.chars().take(100).collect::<String>();
-    println!("[API RESPONSE - Gemini] First 100 chars: {}", truncated_response);
-
-    if let Some(candidates) = response_json.get("candidates") {
-        if let Some(text) = candidates
-            .get(0)
-            .and_then(|c| c.get("content"))
-            .and_then(|c| c.get("parts"))
-            .and_then(|p| p.get(0))
-            .and_then(|p| p.get("text"))
-            .and_then(|t| t.as_str())
-        {
-            Ok(text.to_string())
-        } else {
-            eprintln!("[ERROR - Gemini API] 'text' field missing in candidate: {}", response_string);
-            Ok("I couldn't come up with a response.".to_string())
+/// Logs the outcome of an AI call, records its token usage for `caller` and runs the
+/// answer through the output filter.
+async fn finish_llm_call(provider: &dyn LlmProvider, caller: &Caller, result: Result<llm::LlmResponse, LlmError>) -> Result<String, LlmError> {
+    match result {
+        Ok(response) => {
+            println!(
+                "[API RESPONSE - {}] Finish reason {:?}, tokens {} prompt / {} response.",
+                provider.name(), response.finish_reason, response.usage.prompt_tokens, response.usage.response_tokens
+            );
+            usage::record(caller, response.usage).await;
+            Ok(caller.config.output_filter.apply(&response.text))
+        }
+        Err(e) => {
+            eprintln!("[ERROR - {}] {}", provider.name(), e);
+            Err(e)
         }
-    } else {
-        eprintln!("[ERROR - Gemini API] No candidates found in response: {}", response_string);
-        Ok("I couldn't come up with a response.".to_string())
     }
 }
 
-async fn get_random_fox_gif(api_key: &str) -> Result<String, reqwest::Error> {
-    let client = HttpClient::new();
-    let url = format!("https://tenor.googleapis.com/v2/search?q=fox&key={}&limit=50", api_key);
+async fn record_moderation(ctx: &Context, guild_id: GuildId) {
+    println!("[ACTION] Moderation in Guild (ID: {}), keeping ambient chatter quiet for a while.", guild_id);
+    ctx.data.read().await.get::<AmbientKey>().expect("Expected AmbientKey in TypeMap.").record_moderation(guild_id);
+}
+
+async fn call_llm(provider: &dyn LlmProvider, caller: &Caller, system_instruction: Option<&str>, history: &[HistoryTurn], message: &str) -> Result<String, LlmError> {
+    call_llm_with_parts(provider, caller, system_instruction, history, message, Vec::new(), None).await
+}
+
+/// Like `call_llm`, with extra parts (e.g. downloaded images) attached to the new message,
+/// and optionally with the read-only bot tools on offer.
+async fn call_llm_with_parts(
+    provider: &dyn LlmProvider,
+    caller: &Caller,
+    system_instruction: Option<&str>,
+    history: &[HistoryTurn],
+    message: &str,
+    extra_parts: Vec<ChatPart>,
+    tools: Option<&ToolContext>,
+) -> Result<String, LlmError> {
+    usage::check_quota(caller).await?;
+    println!(
+        "[API REQUEST - {}] Sending request with {} history turns and {} attachments for message: \"{}\"",
+        provider.name(), history.len(), extra_parts.len(), message
+    );
+    let mut chat_message = ChatMessage::user(message);
+    chat_message.parts.extend(extra_parts);
+    let request = build_llm_request(caller, system_instruction, history, chat_message);
+    let result = match tools {
+        Some(tools) => tools::generate_with_tools(provider, request, tools).await,
+        None => provider.generate(&request).await,
+    };
+    finish_llm_call(provider, caller, result).await
+}
+
+/// Asks for an answer shaped by the JSON `schema` and returns the raw JSON text.
+async fn call_llm_structured(provider: &dyn LlmProvider, caller: &Caller, system_instruction: Option<&str>, message: &str, schema: serde_json::Value) -> Result<String, LlmError> {
+    usage::check_quota(caller).await?;
+    println!("[API REQUEST - {}] Sending structured request for message: \"{}\"", provider.name(), message);
+    let mut request = build_llm_request(caller, system_instruction, &[], ChatMessage::user(message));
+    request.response_schema = Some(schema);
+    let result = provider.generate(&request).await;
+    finish_llm_call(provider, caller, result).await
+}
+
+/// Streams an answer into the deferred interaction response, editing it at most once per
+/// `STREAM_EDIT_INTERVAL` to stay clear of Discord's rate limits. Returns the complete
+/// text; the final edit is left to the caller.
+async fn stream_llm_to_interaction(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    prefix: &str,
+    provider: &dyn LlmProvider,
+    caller: &Caller,
+    system_instruction: Option<&str>,
+    message: &str,
+) -> Result<String, LlmError> {
+    usage::check_quota(caller).await?;
+    let request = build_llm_request(caller, system_instruction, &[], ChatMessage::user(message));
+    println!("[API REQUEST - {}] Streaming response for message: \"{}\"", provider.name(), message);
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    let stream = provider.generate_stream(&request, tx);
+    let editor = async {
+        let mut text = String::new();
+        let mut last_edit = Instant::now();
+        while let Some(delta) = rx.recv().await {
+            text.push_str(&delta);
+            if last_edit.elapsed() < STREAM_EDIT_INTERVAL {
+                continue;
+            }
+            let preview: String = format!("{}{}", prefix, caller.config.output_filter.apply(&text)).chars().take(1990).collect();
+            if let Err(e) = command.edit_original_interaction_response(&ctx.http, |response| {
+                response.content(format!("{} ▌", preview)).allowed_mentions(|mentions| mentions.empty_parse())
+            }).await {
+                eprintln!("[ERROR] Could not edit interaction response while streaming: {:?}", e);
+            }
+            last_edit = Instant::now();
+        }
+    };
+
+    let (result, _) = tokio::join!(stream, editor);
+    finish_llm_call(provider, caller, result).await
+}
+
+async fn get_random_fox_gif(tenor: &Upstream) -> Result<String, SendError> {
+    let url = format!("https://tenor.googleapis.com/v2/search?q=fox&key={}&limit=50", tenor.api_key);
     println!("[API REQUEST - Tenor] Sending request to fetch fox GIF.");
-    let response = client.get(&url).send().await?;
-    let response_json: Value = response.json().await?;
+    let response = tenor.send(|http| http.get(&url)).await?;
+    let response_json: Value = response.json().await.map_err(SendError::Request)?;
 
     let response_string = serde_json::to_string(&response_json).unwrap_or_else(|_| "{}".to_string());
     let truncated_response = response_string.chars().take(100).collect::<String>();
@@ -847,6 +1774,71 @@ async fn get_random_fox_gif(api_key: &str) -> Result<String, reqwest::Error> {
         .filter_map(|gif| gif["media_formats"]["gif"]["url"].as_str().map(|s| s.to_string()))
         .collect::<Vec<String>>();
     let mut rng = rand::thread_rng();
-    let random_gif = gifs.choose(&mut rng).unwrap_or(&"https://media.tenor.com/YxT1w3VX5BAAAAAM/fox-dance.gif".to_string()).to_string();
+    let random_gif = gifs.choose(&mut rng).map(|s| s.as_str()).unwrap_or(FALLBACK_FOX_GIF).to_string();
     Ok(random_gif)
 }
+
+#[cfg(test)]
+impl Database {
+    /// A pool that never connects, for tests that don't touch the database. Needs a Tokio
+    /// runtime.
+    fn unconnected() -> Self {
+        let manager = PostgresConnectionManager::new_from_stringlike("host=localhost", NoTls).expect("valid connection string");
+        Database { pool: Arc::new(Pool::builder().build_unchecked(manager)) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use llm::Role;
+
+    fn caller(command: &'static str) -> Caller {
+        Caller {
+            db: Arc::new(Database::unconnected()),
+            config: Arc::new(BotConfig::from_env()),
+            user_id: UserId(1),
+            guild_id: Some(GuildId(2)),
+            command,
+            counts_for_user: true,
+        }
+    }
+
+    fn turn(role: &str, author_name: Option<&str>, content: &str) -> HistoryTurn {
+        HistoryTurn { role: role.to_string(), author_name: author_name.map(str::to_string), content: content.to_string() }
+    }
+
+    #[tokio::test]
+    async fn build_llm_request_replays_history_before_the_message() {
+        let history = [turn("user", Some("alice"), "hi"), turn("model", None, "hello alice")];
+        let request = build_llm_request(&caller("message"), Some("be a fox"), &history, ChatMessage::user("how are you?"));
+
+        assert_eq!(request.system_instruction.as_deref(), Some("be a fox"));
+        let texts: Vec<(Role, String)> = request.messages.iter().map(|m| (m.role, m.text())).collect();
+        assert_eq!(texts, vec![
+            (Role::User, "alice: hi".to_string()),
+            (Role::Model, "hello alice".to_string()),
+            (Role::User, "how are you?".to_string()),
+        ]);
+        assert!(request.tools.is_empty() && request.response_schema.is_none());
+    }
+
+    #[tokio::test]
+    async fn build_llm_request_uses_the_command_settings() {
+        let caller = caller("translate");
+        let request = build_llm_request(&caller, None, &[], ChatMessage::user("text"));
+        assert_eq!(request.settings, caller.config.generation_settings("translate"));
+    }
+
+    #[tokio::test]
+    async fn mock_provider_answers_built_requests_deterministically() {
+        let history = [turn("user", Some("alice"), "hi")];
+        let request = build_llm_request(&caller("nuggies"), None, &history, ChatMessage::user("bob: ping"));
+        let first = MockProvider.generate(&request).await.unwrap();
+        let second = MockProvider.generate(&request).await.unwrap();
+
+        assert_eq!(first.text, "[mock reply to 2 message(s), 0 attachment(s)] bob: ping");
+        assert_eq!(first.text, second.text);
+        assert_eq!(first.usage.prompt_tokens, 4);
+    }
+}
@@ -0,0 +1,155 @@
+//! Nuggies' personality, customizable per guild with `/persona`.
+
+use crate::{Database, DatabaseKey};
+use bb8::RunError;
+use serenity::client::Context;
+use serenity::model::application::component::{ActionRowComponent, InputTextStyle};
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::model::application::interaction::modal::ModalSubmitInteraction;
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::model::guild::Member;
+use serenity::model::id::GuildId;
+use serenity::model::Permissions;
+use tokio_postgres::types::ToSql;
+
+/// Longest personality that can be stored. Matches the maximum length of a modal text input.
+pub const MAX_PERSONALITY_LENGTH: usize = 4000;
+
+pub const MODAL_ID: &str = "persona_modal";
+const MODAL_INPUT_ID: &str = "persona_text";
+
+pub const DEFAULT_PERSONALITY: &str = "You are an Female AI assistant called 'Nuggies'.\
+     You have a somewhat friendly, slightly norse nordic, slightly pagan, sarcastic, quite gothic (NOT EDGY) and somewhat unhinged personality.\
+     dont Roleplay";
+
+/// The guild's custom personality, or the default one outside of guilds, when none is set,
+/// or when the database can't be reached.
+pub async fn get_personality(db: &Database, guild_id: Option<GuildId>) -> String {
+    let guild_id = match guild_id {
+        Some(id) => id,
+        None => return DEFAULT_PERSONALITY.to_string(),
+    };
+    get_custom_personality(db, guild_id).await.unwrap_or_else(|| DEFAULT_PERSONALITY.to_string())
+}
+
+/// The personality set with `/persona set`, if any.
+pub async fn get_custom_personality(db: &Database, guild_id: GuildId) -> Option<String> {
+    let conn = match db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection for personality lookup: {:?}", e);
+            return None;
+        }
+    };
+
+    let guild_id_i64 = guild_id.0 as i64;
+    match conn.query_opt("SELECT personality FROM guild_personas WHERE guild_id = $1", &[&guild_id_i64]).await {
+        Ok(row) => row.map(|r| r.get(0)),
+        Err(e) => {
+            eprintln!("[ERROR] Failed to load personality for Guild (ID: {}): {:?}", guild_id, e);
+            None
+        }
+    }
+}
+
+pub async fn set_personality(db: &Database, guild_id: GuildId, personality: &str, updated_by: u64) -> Result<u64, RunError<tokio_postgres::Error>> {
+    let conn = db.pool.get().await?;
+    let guild_id_i64 = guild_id.0 as i64;
+    let updated_by_i64 = updated_by as i64;
+    let params: &[&(dyn ToSql + Sync)] = &[&guild_id_i64, &personality, &updated_by_i64];
+    Ok(conn.execute(
+        "INSERT INTO guild_personas (guild_id, personality, updated_by, updated_at) VALUES ($1, $2, $3, NOW())
+         ON CONFLICT (guild_id) DO UPDATE SET personality = EXCLUDED.personality, updated_by = EXCLUDED.updated_by, updated_at = NOW()",
+        params,
+    ).await?)
+}
+
+pub async fn reset_personality(db: &Database, guild_id: GuildId) -> Result<u64, RunError<tokio_postgres::Error>> {
+    let conn = db.pool.get().await?;
+    let guild_id_i64 = guild_id.0 as i64;
+    Ok(conn.execute("DELETE FROM guild_personas WHERE guild_id = $1", &[&guild_id_i64]).await?)
+}
+
+/// Discord already hides `/persona` from members without Manage Server, but server admins
+/// can override that in the integration settings, so the commands check again.
+pub fn can_manage(member: Option<&Member>) -> bool {
+    member.and_then(|m| m.permissions).is_some_and(|p| p.contains(Permissions::MANAGE_GUILD))
+}
+
+/// Checks a new personality and saves it, returning the reply for the admin.
+pub async fn save_personality(db: &Database, guild_id: GuildId, personality: &str, updated_by: u64) -> String {
+    let personality = personality.trim();
+    if personality.is_empty() {
+        return "The personality can't be empty. Use `/persona reset` to go back to the default one.".to_string();
+    }
+    if personality.chars().count() > MAX_PERSONALITY_LENGTH {
+        return format!("That personality is too long. Keep it under {} characters.", MAX_PERSONALITY_LENGTH);
+    }
+
+    match set_personality(db, guild_id, personality, updated_by).await {
+        Ok(_) => {
+            println!("[ACTION] User (ID: {}) set a new personality for Guild (ID: {}).", updated_by, guild_id);
+            "Done. That's who I am in this server from now on.".to_string()
+        }
+        Err(e) => {
+            eprintln!("[ERROR] Failed to save personality for Guild (ID: {}): {:?}", guild_id, e);
+            "Sorry, I couldn't save the new personality right now.".to_string()
+        }
+    }
+}
+
+/// Answers `/persona set` without a `text` option with a modal, which has room for a
+/// much longer personality than a slash command option. It is prefilled with the current one.
+pub async fn show_modal(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let db = ctx.data.read().await.get::<DatabaseKey>().expect("Expected DatabaseKey in TypeMap.").clone();
+    let current = get_personality(&db, command.guild_id).await;
+
+    if let Err(e) = command.create_interaction_response(&ctx.http, |response| {
+        response.kind(InteractionResponseType::Modal).interaction_response_data(|data| {
+            data.custom_id(MODAL_ID).title("Nuggies' personality").components(|components| {
+                components.create_action_row(|row| {
+                    row.create_input_text(|input| {
+                        input.custom_id(MODAL_INPUT_ID)
+                            .style(InputTextStyle::Paragraph)
+                            .label("Personality")
+                            .placeholder("You are an AI assistant called 'Nuggies'...")
+                            .min_length(1)
+                            .max_length(MAX_PERSONALITY_LENGTH as u64)
+                            .required(true)
+                            .value(&current)
+                    })
+                })
+            })
+        })
+    }).await {
+        eprintln!("[ERROR] Could not show persona modal: {:?}", e);
+    }
+}
+
+pub async fn handle_modal_submit(ctx: &Context, modal: &ModalSubmitInteraction) {
+    println!("[MODAL] Received persona modal from user '{}' (ID: {}) in Guild (ID: {:?}).", modal.user.name, modal.user.id, modal.guild_id);
+
+    let personality = modal.data.components.iter()
+        .flat_map(|row| row.components.iter())
+        .find_map(|component| match component {
+            ActionRowComponent::InputText(input) if input.custom_id == MODAL_INPUT_ID => Some(input.value.clone()),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    let content = match modal.guild_id {
+        None => "Personalities can only be changed in a server.".to_string(),
+        Some(_) if !can_manage(modal.member.as_ref()) => "You need the Manage Server permission to change my personality.".to_string(),
+        Some(guild_id) => {
+            let db = ctx.data.read().await.get::<DatabaseKey>().expect("Expected DatabaseKey in TypeMap.").clone();
+            save_personality(&db, guild_id, &personality, modal.user.id.0).await
+        }
+    };
+
+    if let Err(e) = modal.create_interaction_response(&ctx.http, |response| {
+        response.kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|data| data.content(content).ephemeral(true))
+    }).await {
+        eprintln!("[ERROR] Could not respond to persona modal: {:?}", e);
+    }
+}
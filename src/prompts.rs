@@ -0,0 +1,153 @@
+//! Prompt templates for the AI commands, kept in a TOML file (`NUGGIES_PROMPTS_FILE`,
+//! `prompts.toml` by default) so their wording can be tuned without recompiling. The file
+//! is validated when it is loaded and checked for changes every few seconds; a changed
+//! file that doesn't validate is rejected and the previous templates stay in use.
+
+use regex::Regex;
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// The templates compiled into the binary, used when there is no templates file.
+const DEFAULT_TEMPLATES: &str = include_str!("../prompts.toml");
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Every template with the variables it must use and the ones it may use. `user_text_note`
+/// may be used by all of them.
+const TEMPLATES: &[(&str, &[&str], &[&str])] = &[
+    ("message", &["message"], &["facts", "thread"]),
+    ("nuggies", &["message"], &["facts"]),
+    ("ask", &["question"], &[]),
+    ("translate", &["language", "text"], &[]),
+    ("auto_translate", &["languages", "text"], &[]),
+    ("funfact_random", &[], &[]),
+    ("funfact_topic", &["topic"], &[]),
+    ("slots_win", &[], &["winnings"]),
+    ("slots_even", &[], &["bet"]),
+    ("slots_loss", &[], &["bet"]),
+    ("ambient", &["conversation"], &[]),
+    ("summarize", &["transcript"], &[]),
+    ("summarize_part", &["part"], &["number", "count"]),
+    ("summarize_combine", &["parts"], &[]),
+];
+
+fn variable_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| Regex::new(r"\{([a-z_]+)\}").expect("variable regex is valid"))
+}
+
+/// Parses and validates templates, describing every problem found if they don't validate.
+fn parse(text: &str) -> Result<HashMap<String, String>, String> {
+    let templates: HashMap<String, String> = toml::from_str(text).map_err(|e| e.to_string())?;
+    let mut problems = Vec::new();
+
+    for name in templates.keys() {
+        if !TEMPLATES.iter().any(|(known, _, _)| known == name) {
+            problems.push(format!("unknown template '{}'", name));
+        }
+    }
+    for (name, required, optional) in TEMPLATES {
+        let Some(template) = templates.get(*name) else {
+            problems.push(format!("missing template '{}'", name));
+            continue;
+        };
+        let used: Vec<&str> = variable_regex().captures_iter(template).map(|c| c.get(1).map_or("", |m| m.as_str())).collect();
+        for variable in &used {
+            if *variable != "user_text_note" && !required.contains(variable) && !optional.contains(variable) {
+                problems.push(format!("'{}' uses unknown variable {{{}}}", name, variable));
+            }
+        }
+        for variable in *required {
+            if !used.contains(variable) {
+                problems.push(format!("'{}' must use {{{}}}", name, variable));
+            }
+        }
+    }
+
+    if problems.is_empty() { Ok(templates) } else { Err(problems.join(", ")) }
+}
+
+pub struct Prompts {
+    path: PathBuf,
+    templates: RwLock<Arc<HashMap<String, String>>>,
+    modified: RwLock<Option<SystemTime>>,
+}
+
+impl Prompts {
+    /// Loads the templates file, or the built-in templates if there is none. Panics if the
+    /// file exists but doesn't validate, so a broken file is noticed before going live.
+    pub fn from_env() -> Self {
+        let path = PathBuf::from(env::var("NUGGIES_PROMPTS_FILE").unwrap_or_else(|_| "prompts.toml".to_string()));
+        let (templates, modified) = match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                let templates = parse(&text).unwrap_or_else(|e| panic!("Invalid prompt templates in {}: {}", path.display(), e));
+                println!("[INFO] Loaded prompt templates from {}.", path.display());
+                (templates, modified_time(&path))
+            }
+            Err(_) => {
+                println!("[INFO] No prompt templates at {}, using the built-in ones.", path.display());
+                (parse(DEFAULT_TEMPLATES).expect("built-in prompt templates are valid"), None)
+            }
+        };
+        Prompts { path, templates: RwLock::new(Arc::new(templates)), modified: RwLock::new(modified) }
+    }
+
+    /// Fills in the template `name`. User text in `variables` should already be delimited.
+    pub fn render(&self, name: &str, variables: &[(&str, &str)]) -> String {
+        let templates = self.templates.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        let template = templates.get(name).map(String::as_str).unwrap_or_else(|| panic!("no prompt template named '{}'", name));
+        variable_regex().replace_all(template, |captures: &regex::Captures| {
+            let variable = &captures[1];
+            if variable == "user_text_note" {
+                return crate::safety::USER_TEXT_NOTE.to_string();
+            }
+            variables.iter().find(|(known, _)| *known == variable).map_or_else(String::new, |(_, value)| value.to_string())
+        }).into_owned()
+    }
+
+    /// Reloads the templates if the file changed since it was last read.
+    fn reload_if_changed(&self) {
+        let modified = modified_time(&self.path);
+        if modified.is_none() || modified == *self.modified.read().unwrap_or_else(|poisoned| poisoned.into_inner()) {
+            return;
+        }
+        *self.modified.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = modified;
+
+        match std::fs::read_to_string(&self.path).map_err(|e| e.to_string()).and_then(|text| parse(&text)) {
+            Ok(templates) => {
+                *self.templates.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(templates);
+                println!("[INFO] Reloaded prompt templates from {}.", self.path.display());
+            }
+            Err(e) => eprintln!("[ERROR] Keeping the previous prompt templates, {} is invalid: {}", self.path.display(), e),
+        }
+    }
+
+    /// Checks the templates file for changes for as long as the bot runs.
+    pub fn watch(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+            loop {
+                interval.tick().await;
+                self.reload_if_changed();
+            }
+        });
+    }
+}
+
+fn modified_time(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_templates_validate() {
+        if let Err(problems) = parse(DEFAULT_TEMPLATES) {
+            panic!("prompts.toml doesn't validate: {}", problems);
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Posting replies that may be longer than Discord's 2000 character message limit.
+//! Long text is split into Markdown-aware chunks and posted as several messages; if that
+//! would take more than the configured number of messages, the full text is attached as a
+//! Markdown file instead. Nothing posted from here can ping anyone.
+
+use serenity::client::Context;
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::model::channel::{AttachmentType, Message};
+use serenity::model::id::ChannelId;
+use std::borrow::Cow;
+
+pub const DISCORD_MESSAGE_LIMIT: usize = 2000;
+
+const CODE_FENCE: &str = "```";
+/// Posted instead of an empty message, which Discord rejects.
+const EMPTY_PLACEHOLDER: &str = "*(nothing to say)*";
+const ATTACHMENT_NOTE: &str = "That was a bit long for Discord, so here's the whole thing as a file.";
+
+/// Splits `text` into chunks of at most `limit` characters. Splits happen at line breaks
+/// where possible, otherwise at whitespace, and only mid-word if a single word is longer
+/// than a whole message. A code block that spans a split is closed at the end of one chunk
+/// and reopened (with the same language tag) at the start of the next. Empty text becomes a
+/// single placeholder chunk.
+pub fn split_message(text: &str, limit: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut open_fence: Option<String> = None;
+
+    for line in text.split('\n') {
+        // Inside a code block, leave room for reopening and closing the fence around every piece.
+        let reserve = open_fence.as_ref().map_or(0, |f| char_len(f) + 1 + CODE_FENCE.len() + 1);
+        for piece in split_line(line, limit.saturating_sub(reserve).max(1)) {
+            let closing = if open_fence.is_some() { CODE_FENCE.len() + 1 } else { 0 };
+            let needed = char_len(&current) + usize::from(!current.is_empty()) + char_len(piece) + closing;
+            if needed > limit && !current.is_empty() {
+                flush(&mut chunks, &mut current, open_fence.as_deref());
+            }
+            if !current.is_empty() {
+                current.push('\n');
+            }
+            current.push_str(piece);
+        }
+
+        let trimmed = line.trim_start();
+        if trimmed.starts_with(CODE_FENCE) {
+            open_fence = match open_fence {
+                Some(_) => None,
+                None => Some(trimmed.to_string()),
+            };
+        }
+    }
+
+    if !current.trim().is_empty() {
+        chunks.push(current);
+    }
+    if chunks.is_empty() {
+        chunks.push(EMPTY_PLACEHOLDER.to_string());
+    }
+    chunks
+}
+
+fn char_len(s: &str) -> usize {
+    s.chars().count()
+}
+
+/// Ends the current chunk (closing an open code block) and starts the next one
+/// (reopening it).
+fn flush(chunks: &mut Vec<String>, current: &mut String, open_fence: Option<&str>) {
+    let mut chunk = std::mem::take(current);
+    if let Some(fence) = open_fence {
+        chunk.push('\n');
+        chunk.push_str(CODE_FENCE);
+        current.push_str(fence);
+    }
+    if !chunk.trim().is_empty() {
+        chunks.push(chunk);
+    }
+}
+
+/// Breaks a single line into pieces of at most `limit` characters, preferring whitespace.
+fn split_line(line: &str, limit: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = line;
+    while char_len(rest) > limit {
+        let hard_end = rest.char_indices().nth(limit).map_or(rest.len(), |(i, _)| i);
+        let end = match rest[..hard_end].rfind(char::is_whitespace) {
+            Some(space) if space > 0 => space,
+            _ => hard_end,
+        };
+        pieces.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    pieces.push(rest);
+    pieces
+}
+
+fn as_attachment(text: &str) -> AttachmentType<'static> {
+    AttachmentType::Bytes { data: Cow::Owned(text.as_bytes().to_vec()), filename: "nuggies-answer.md".to_string() }
+}
+
+/// Like `ChannelId::say`, but the message can't ping anyone, whatever it contains. With
+/// `reference`, it is posted as a reply to that message.
+async fn say(ctx: &Context, channel_id: ChannelId, reference: Option<&Message>, content: &str) -> serenity::Result<Message> {
+    channel_id.send_message(&ctx.http, |m| {
+        if let Some(reference) = reference {
+            m.reference_message(reference);
+        }
+        m.content(content).allowed_mentions(|mentions| mentions.empty_parse())
+    }).await
+}
+
+/// Puts `content` into the (deferred) original interaction response, posting whatever
+/// doesn't fit as follow-up messages.
+pub async fn edit_interaction_reply(ctx: &Context, command: &ApplicationCommandInteraction, content: &str, max_chunks: usize) {
+    let chunks = split_message(content, DISCORD_MESSAGE_LIMIT);
+    let first = chunks.first().cloned().unwrap_or_default();
+
+    if let Err(e) = command.edit_original_interaction_response(&ctx.http, |response| {
+        response.content(&first).allowed_mentions(|mentions| mentions.empty_parse())
+    }).await {
+        eprintln!("[ERROR] Could not edit interaction response: {:?}", e);
+        return;
+    }
+
+    if chunks.len() > max_chunks {
+        println!("[ACTION] Reply needs {} messages, attaching it as a file instead.", chunks.len());
+        if let Err(e) = command.create_followup_message(&ctx.http, |followup| {
+            followup.content(ATTACHMENT_NOTE).add_file(as_attachment(content)).allowed_mentions(|mentions| mentions.empty_parse())
+        }).await {
+            eprintln!("[ERROR] Could not send follow-up file: {:?}", e);
+        }
+        return;
+    }
+
+    for chunk in chunks.iter().skip(1) {
+        if let Err(e) = command.create_followup_message(&ctx.http, |followup| {
+            followup.content(chunk).allowed_mentions(|mentions| mentions.empty_parse())
+        }).await {
+            eprintln!("[ERROR] Could not send follow-up message: {:?}", e);
+            return;
+        }
+    }
+}
+
+/// Posts `content` to a channel as one or more messages.
+pub async fn send_channel_reply(ctx: &Context, channel_id: ChannelId, content: &str, max_chunks: usize) {
+    send_chunks(ctx, channel_id, None, content, max_chunks).await;
+}
+
+/// Posts `content` as a Discord reply to `message`; any further messages follow normally.
+pub async fn send_reply_to(ctx: &Context, message: &Message, content: &str, max_chunks: usize) {
+    send_chunks(ctx, message.channel_id, Some(message), content, max_chunks).await;
+}
+
+async fn send_chunks(ctx: &Context, channel_id: ChannelId, reference: Option<&Message>, content: &str, max_chunks: usize) {
+    let chunks = split_message(content, DISCORD_MESSAGE_LIMIT);
+
+    if chunks.len() > max_chunks {
+        println!("[ACTION] Reply needs {} messages, attaching it as a file instead.", chunks.len());
+        let first = chunks.first().cloned().unwrap_or_default();
+        if let Err(e) = say(ctx, channel_id, reference, &first).await {
+            eprintln!("[ERROR] Failed to send message to channel (ID: {}): {:?}", channel_id, e);
+            return;
+        }
+        if let Err(e) = channel_id.send_message(&ctx.http, |m| {
+            m.content(ATTACHMENT_NOTE).add_file(as_attachment(content)).allowed_mentions(|mentions| mentions.empty_parse())
+        }).await {
+            eprintln!("[ERROR] Failed to send file to channel (ID: {}): {:?}", channel_id, e);
+        }
+        return;
+    }
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        if let Err(e) = say(ctx, channel_id, reference.filter(|_| i == 0), chunk).await {
+            eprintln!("[ERROR] Failed to send message to channel (ID: {}): {:?}", channel_id, e);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_stay_within_the_limit_and_keep_every_word() {
+        let text = (0..1500).map(|i| format!("word{}", i)).collect::<Vec<_>>().join(" ");
+        let chunks = split_message(&text, DISCORD_MESSAGE_LIMIT);
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| char_len(chunk) <= DISCORD_MESSAGE_LIMIT));
+        assert_eq!(chunks.join(" ").split_whitespace().collect::<Vec<_>>(), text.split_whitespace().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn short_text_is_a_single_chunk() {
+        assert_eq!(split_message("hello\nworld", DISCORD_MESSAGE_LIMIT), vec!["hello\nworld".to_string()]);
+    }
+
+    #[test]
+    fn words_are_not_split() {
+        let text = "alpha bravo charlie delta echo foxtrot golf hotel india juliet";
+        let words: Vec<&str> = text.split(' ').collect();
+        for chunk in split_message(text, 16) {
+            assert!(char_len(&chunk) <= 16);
+            assert!(chunk.split_whitespace().all(|word| words.contains(&word)), "split mid-word: {:?}", chunk);
+        }
+    }
+
+    #[test]
+    fn over_long_words_are_cut_as_a_last_resort() {
+        let word = "a".repeat(50);
+        assert_eq!(split_message(&word, 20), vec!["a".repeat(20), "a".repeat(20), "a".repeat(10)]);
+    }
+
+    #[test]
+    fn code_blocks_are_closed_and_reopened_with_their_language() {
+        let code: Vec<String> = (0..20).map(|i| format!("let x{} = {};", i, i)).collect();
+        let text = format!("Here you go:\n```rust\n{}\n```\nDone.", code.join("\n"));
+        let chunks = split_message(&text, 80);
+
+        assert!(chunks.len() > 2);
+        for (i, chunk) in chunks.iter().enumerate() {
+            assert!(char_len(chunk) <= 80);
+            assert_eq!(chunk.matches(CODE_FENCE).count() % 2, 0, "unbalanced fence in chunk {}: {:?}", i, chunk);
+        }
+        assert!(chunks[1..chunks.len() - 1].iter().all(|chunk| chunk.starts_with("```rust\n")));
+        assert!(chunks.last().unwrap().ends_with("Done."));
+    }
+
+    #[test]
+    fn empty_text_becomes_a_placeholder() {
+        assert_eq!(split_message("", DISCORD_MESSAGE_LIMIT), vec![EMPTY_PLACEHOLDER.to_string()]);
+        assert_eq!(split_message("  \n ", DISCORD_MESSAGE_LIMIT), vec![EMPTY_PLACEHOLDER.to_string()]);
+    }
+}
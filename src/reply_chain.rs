@@ -0,0 +1,59 @@
+//! Context for messages that use Discord's reply feature on one of Nuggies' messages: the
+//! chain of messages being replied to, followed back a few hops.
+
+use crate::safety;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::UserId;
+
+/// Longest piece of a single message that is included, in characters.
+const MAX_MESSAGE_CHARS: usize = 1000;
+
+/// Whether `msg` is a reply to a message written by the bot itself.
+pub fn is_reply_to_bot(msg: &Message, bot_id: UserId) -> bool {
+    msg.referenced_message.as_ref().is_some_and(|referenced| referenced.author.id == bot_id)
+}
+
+/// The messages `msg` replies to, oldest first, following at most `max_hops` references.
+/// Discord only includes the first one with the event, so the rest are fetched.
+pub async fn collect(ctx: &Context, msg: &Message, max_hops: usize) -> Vec<Message> {
+    let mut chain = Vec::new();
+    let mut next = msg.referenced_message.as_deref().cloned();
+
+    while let Some(current) = next.take() {
+        if chain.len() >= max_hops {
+            break;
+        }
+        next = match (&current.referenced_message, &current.message_reference) {
+            (Some(parent), _) => Some((**parent).clone()),
+            (None, Some(reference)) => match reference.message_id {
+                Some(parent_id) => match reference.channel_id.message(&ctx.http, parent_id).await {
+                    Ok(parent) => Some(parent),
+                    Err(e) => {
+                        eprintln!("[ERROR] Could not fetch replied-to message (ID: {}): {:?}", parent_id, e);
+                        None
+                    }
+                },
+                None => None,
+            },
+            (None, None) => None,
+        };
+        chain.push(current);
+    }
+
+    chain.reverse();
+    chain
+}
+
+/// The chain as delimited prompt text, with Nuggies' own messages labelled as hers.
+pub fn render(chain: &[Message], bot_id: UserId) -> String {
+    let lines: Vec<String> = chain.iter().map(|message| {
+        let author = if message.author.id == bot_id { "Nuggies" } else { message.author.name.as_str() };
+        let content: String = message.content.chars().take(MAX_MESSAGE_CHARS).collect();
+        format!("{}: {}", author, content)
+    }).collect();
+    format!(
+        "The message is a reply to this thread of earlier messages (oldest first):\n{}",
+        safety::delimit_user_text(&lines.join("\n"))
+    )
+}
@@ -0,0 +1,52 @@
+//! Quips for `/slots`. The reels are shown right away and Nuggies' AI quip is edited in
+//! afterwards. When the AI fails, a quip from the local bank is used instead; when it is
+//! slow, the local quip is shown until the AI's one arrives.
+
+use rand::seq::SliceRandom;
+use std::time::Duration;
+
+/// How long the AI quip may take before a local one is shown in the meantime.
+pub const QUIP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Win,
+    BreakEven,
+    Loss,
+}
+
+const WIN_QUIPS: &[&str] = &[
+    "Look at you, robbing my machine blind. Odin would be proud. Or horrified.",
+    "Enjoy it while it lasts. The house always wins eventually, and I am the house.",
+    "Fine, take your nuggets. I'll just sit here in the dark and recount what's left.",
+    "The foxes are furious. Sleep with one eye open tonight.",
+    "Beginner's luck, obviously. Nobody is actually good at pulling a lever.",
+    "Congratulations. I'm contractually obliged to say that.",
+];
+
+const BREAK_EVEN_QUIPS: &[&str] = &[
+    "All that suspense for exactly nothing. Riveting.",
+    "You got your nuggets back. Thrilling. Truly the saga of our age.",
+    "Neither glory nor ruin. The most Tuesday outcome possible.",
+    "The machine spared you. It won't be so merciful next time.",
+    "Even the fox couldn't be bothered this round.",
+];
+
+const LOSS_QUIPS: &[&str] = &[
+    "A fox ate your nuggets. It says thank you.",
+    "Your nuggets have gone to Valhalla. They died bravely, at least.",
+    "The reels have spoken, and they said no.",
+    "That's going straight into the fox fund. Much appreciated.",
+    "Gone. Poof. Like they were never yours to begin with.",
+    "Have you considered a hobby that doesn't involve feeding foxes?",
+];
+
+/// A random quip from the local bank.
+pub fn fallback_quip(outcome: Outcome) -> &'static str {
+    let quips = match outcome {
+        Outcome::Win => WIN_QUIPS,
+        Outcome::BreakEven => BREAK_EVEN_QUIPS,
+        Outcome::Loss => LOSS_QUIPS,
+    };
+    quips.choose(&mut rand::thread_rng()).copied().unwrap_or("...")
+}
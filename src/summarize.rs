@@ -0,0 +1,151 @@
+//! `/summarize`: catching up on a channel. Recent messages are turned into a compact
+//! transcript; long transcripts are summarized in chunks first (map) and the partial
+//! summaries then combined into one (reduce).
+
+use crate::call_llm;
+use crate::llm::{LlmError, LlmProvider};
+use crate::prompts::Prompts;
+use crate::safety;
+use crate::usage::Caller;
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Europe::Berlin;
+use serenity::client::Context;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
+use std::collections::HashMap;
+
+pub const DEFAULT_MESSAGES: u64 = 100;
+pub const MAX_MESSAGES: u64 = 500;
+/// How far back a time span may reach, in messages, so "the last 72 hours" stays bounded.
+const MAX_SCANNED_MESSAGES: u64 = 1000;
+/// Transcripts longer than this are summarized in chunks of about this size.
+const CHUNK_CHARS: usize = 12_000;
+const MAX_MESSAGE_CHARS: usize = 500;
+const PAGE_SIZE: u64 = 100;
+
+/// Which messages to summarize: the last `limit` messages, or everything since `since`,
+/// optionally only those written by `user`.
+pub struct Selection {
+    pub limit: u64,
+    pub since: Option<DateTime<Utc>>,
+    pub user: Option<UserId>,
+}
+
+fn timestamp(message: &Message) -> DateTime<Utc> {
+    Utc.timestamp_opt(message.timestamp.unix_timestamp(), 0).single().unwrap_or_else(Utc::now)
+}
+
+/// The selected messages, oldest first.
+pub async fn fetch_messages(ctx: &Context, channel_id: ChannelId, selection: &Selection) -> serenity::Result<Vec<Message>> {
+    let wanted = if selection.since.is_some() { MAX_SCANNED_MESSAGES } else { selection.limit.min(MAX_MESSAGES) };
+    let mut messages: Vec<Message> = Vec::new();
+    let mut before: Option<MessageId> = None;
+
+    'pages: while (messages.len() as u64) < wanted {
+        let page_size = PAGE_SIZE.min(wanted - messages.len() as u64);
+        let page = channel_id.messages(&ctx.http, |retriever| {
+            if let Some(before) = before {
+                retriever.before(before);
+            }
+            retriever.limit(page_size)
+        }).await?;
+        let page_len = page.len() as u64;
+        before = page.last().map(|m| m.id);
+
+        for message in page {
+            if selection.since.is_some_and(|since| timestamp(&message) < since) {
+                break 'pages;
+            }
+            messages.push(message);
+        }
+        if page_len < page_size {
+            break;
+        }
+    }
+
+    messages.retain(|m| selection.user.is_none_or(|user| m.author.id == user));
+    messages.reverse();
+    Ok(messages)
+}
+
+/// One line per message: "[18:42] Name: text". Server nicknames are used where possible.
+pub async fn build_transcript(ctx: &Context, guild_id: Option<GuildId>, messages: &[Message], bot_id: Option<UserId>) -> Vec<String> {
+    let mut names: HashMap<UserId, String> = HashMap::new();
+    let mut lines = Vec::new();
+
+    for message in messages {
+        let mut text: String = message.content.chars().take(MAX_MESSAGE_CHARS).collect();
+        for attachment in &message.attachments {
+            text.push_str(&format!(" [attached: {}]", attachment.filename));
+        }
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        let name = match names.get(&message.author.id) {
+            Some(name) => name.clone(),
+            None => {
+                let name = if Some(message.author.id) == bot_id {
+                    "Nuggies".to_string()
+                } else {
+                    match guild_id {
+                        Some(guild_id) => match guild_id.member(&ctx.http, message.author.id).await {
+                            Ok(member) => member.display_name().to_string(),
+                            Err(_) => message.author.name.clone(),
+                        },
+                        None => message.author.name.clone(),
+                    }
+                };
+                names.insert(message.author.id, name.clone());
+                name
+            }
+        };
+
+        let time = timestamp(message).with_timezone(&Berlin).format("%H:%M");
+        lines.push(format!("[{}] {}: {}", time, name, text));
+    }
+    lines
+}
+
+/// Groups transcript lines into chunks of at most about `CHUNK_CHARS` characters.
+fn chunk_lines(lines: &[String]) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + line.len() + 1 > CHUNK_CHARS {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// Summarizes the transcript in Nuggies' voice (`persona`), going through partial summaries
+/// if it is too long for a single request.
+pub async fn summarize(provider: &dyn LlmProvider, caller: &Caller, prompts: &Prompts, persona: &str, lines: &[String]) -> Result<String, LlmError> {
+    let chunks = chunk_lines(lines);
+    if chunks.len() == 1 {
+        let prompt = prompts.render("summarize", &[("transcript", &safety::delimit_user_text(&chunks[0]))]);
+        return call_llm(provider, caller, Some(persona), &[], &prompt).await;
+    }
+
+    println!("[ACTION] Summarizing a long transcript in {} chunks.", chunks.len());
+    let mut partials = Vec::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let prompt = prompts.render("summarize_part", &[
+            ("number", &(i + 1).to_string()),
+            ("count", &chunks.len().to_string()),
+            ("part", &safety::delimit_user_text(chunk)),
+        ]);
+        partials.push(call_llm(provider, caller, None, &[], &prompt).await?);
+    }
+
+    let prompt = prompts.render("summarize_combine", &[("parts", &safety::delimit_user_text(&partials.join("\n\n")))]);
+    call_llm(provider, caller, Some(persona), &[], &prompt).await
+}
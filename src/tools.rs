@@ -0,0 +1,258 @@
+//! Read-only tools Nuggies can call while chatting, so she can answer questions about bot
+//! data ("how many nuggets do I have?") instead of making something up. The model asks for
+//! a tool, the bot runs it and sends the result back, until the model answers with text.
+
+use crate::llm::{ChatMessage, ChatPart, LlmError, LlmProvider, LlmRequest, LlmResponse, Role, ToolCall, ToolChoice, ToolDeclaration};
+use crate::upstream::Upstream;
+use crate::{get_random_fox_gif, Database};
+use chrono::Utc;
+use chrono_tz::Europe::Berlin;
+use serde_json::{json, Value};
+use serenity::model::id::UserId;
+use std::sync::Arc;
+
+/// How many times the model may call tools before it has to answer with what it has.
+const MAX_TOOL_ROUNDS: usize = 4;
+const MAX_LEADERBOARD_ENTRIES: i64 = 10;
+
+/// What the tools need to run: bot data and who is asking.
+pub struct ToolContext {
+    pub db: Arc<Database>,
+    pub tenor: Arc<Upstream>,
+    pub user_id: UserId,
+}
+
+pub fn declarations() -> Vec<ToolDeclaration> {
+    vec![
+        ToolDeclaration {
+            name: "get_nugget_balance",
+            description: "Look up how many nuggets (the server currency) a user has. Defaults to the user you are talking to.",
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {
+                    "user_id": {
+                        "type": "string",
+                        "description": "Discord user ID to look up. Leave out for the user you are talking to."
+                    }
+                }
+            })),
+        },
+        ToolDeclaration {
+            name: "get_leaderboard",
+            description: "Get the users with the most nuggets, richest first.",
+            parameters: Some(json!({
+                "type": "object",
+                "properties": {
+                    "limit": {
+                        "type": "integer",
+                        "description": "How many users to return (1-10, defaults to 10)."
+                    }
+                }
+            })),
+        },
+        ToolDeclaration {
+            name: "get_berlin_time",
+            description: "Get the current date and time in Berlin, the server's time zone.",
+            parameters: None,
+        },
+        ToolDeclaration {
+            name: "get_random_fox_gif",
+            description: "Get the URL of a random fox GIF.",
+            parameters: None,
+        },
+    ]
+}
+
+/// Runs a tool call. Failures are reported to the model as `{"error": ...}` rather than
+/// ending the conversation, so it can tell the user what went wrong.
+pub async fn execute(call: &ToolCall, tools: &ToolContext) -> Value {
+    println!("[TOOL] Model called '{}' with arguments {}", call.name, call.args);
+    let result = match call.name.as_str() {
+        "get_nugget_balance" => nugget_balance(call, tools).await,
+        "get_leaderboard" => leaderboard(call, tools).await,
+        "get_berlin_time" => {
+            let now = Utc::now().with_timezone(&Berlin);
+            Ok(json!({ "time": now.format("%Y-%m-%d %H:%M:%S %Z").to_string(), "weekday": now.format("%A").to_string() }))
+        }
+        "get_random_fox_gif" => match get_random_fox_gif(&tools.tenor).await {
+            Ok(url) => Ok(json!({ "url": url })),
+            Err(e) => {
+                eprintln!("[ERROR] Fox GIF tool failed: {:?}", e);
+                Err("Tenor is not reachable right now.".to_string())
+            }
+        },
+        other => Err(format!("There is no tool called '{}'.", other)),
+    };
+    result.unwrap_or_else(|error| json!({ "error": error }))
+}
+
+async fn nugget_balance(call: &ToolCall, tools: &ToolContext) -> Result<Value, String> {
+    let user_id = match &call.args["user_id"] {
+        Value::String(id) => id.trim_matches(|c: char| !c.is_ascii_digit()).parse::<u64>().map_err(|_| format!("'{}' is not a Discord user ID.", id))?,
+        Value::Number(id) => id.as_u64().ok_or_else(|| format!("'{}' is not a Discord user ID.", id))?,
+        _ => tools.user_id.0,
+    };
+
+    let conn = tools.db.pool.get().await.map_err(|e| {
+        eprintln!("[ERROR] Failed to get DB connection for balance tool: {:?}", e);
+        "The database is not reachable right now.".to_string()
+    })?;
+    let user_id_i64 = user_id as i64;
+    match conn.query_opt("SELECT nuggets FROM users WHERE user_id = $1", &[&user_id_i64]).await {
+        Ok(Some(row)) => Ok(json!({ "user_id": user_id.to_string(), "nuggets": row.get::<_, i64>(0) })),
+        Ok(None) => Ok(json!({ "user_id": user_id.to_string(), "nuggets": null, "note": "This user has no nuggetbox yet. They can get one with /daily." })),
+        Err(e) => {
+            eprintln!("[ERROR] Balance tool query failed: {:?}", e);
+            Err("The balance lookup failed.".to_string())
+        }
+    }
+}
+
+async fn leaderboard(call: &ToolCall, tools: &ToolContext) -> Result<Value, String> {
+    let limit = call.args["limit"].as_i64().unwrap_or(MAX_LEADERBOARD_ENTRIES).clamp(1, MAX_LEADERBOARD_ENTRIES);
+
+    let conn = tools.db.pool.get().await.map_err(|e| {
+        eprintln!("[ERROR] Failed to get DB connection for leaderboard tool: {:?}", e);
+        "The database is not reachable right now.".to_string()
+    })?;
+    match conn.query("SELECT user_id, nuggets FROM users ORDER BY nuggets DESC LIMIT $1", &[&limit]).await {
+        Ok(rows) => {
+            let entries: Vec<Value> = rows.iter().enumerate().map(|(i, row)| json!({
+                "rank": i + 1,
+                "user_id": row.get::<_, i64>(0).to_string(),
+                "nuggets": row.get::<_, i64>(1),
+            })).collect();
+            Ok(json!({ "leaderboard": entries }))
+        }
+        Err(e) => {
+            eprintln!("[ERROR] Leaderboard tool query failed: {:?}", e);
+            Err("The leaderboard lookup failed.".to_string())
+        }
+    }
+}
+
+/// Like `provider.generate`, with the tools above on offer. Tool calls are run and their
+/// results sent back until the model answers with text. The returned usage covers all rounds.
+pub async fn generate_with_tools(provider: &dyn LlmProvider, mut request: LlmRequest, tools: &ToolContext) -> Result<LlmResponse, LlmError> {
+    request.tools = declarations();
+    let mut usage = Default::default();
+    let mut rounds = 0;
+
+    loop {
+        if rounds == MAX_TOOL_ROUNDS {
+            request.tool_choice = ToolChoice::None;
+        }
+        let mut response = provider.generate(&request).await?;
+        usage += response.usage;
+        if response.tool_calls.is_empty() || request.tool_choice == ToolChoice::None {
+            if response.text.trim().is_empty() {
+                return Err(LlmError::EmptyResponse { finish_reason: response.finish_reason });
+            }
+            response.usage = usage;
+            response.tool_calls.clear();
+            return Ok(response);
+        }
+
+        let mut call_parts = Vec::new();
+        if !response.text.trim().is_empty() {
+            call_parts.push(ChatPart::Text(response.text.clone()));
+        }
+        let mut result_parts = Vec::new();
+        for call in &response.tool_calls {
+            call_parts.push(ChatPart::ToolCall(call.clone()));
+            result_parts.push(ChatPart::ToolResult { id: call.id.clone(), name: call.name.clone(), result: execute(call, tools).await });
+        }
+        request.messages.push(ChatMessage { role: Role::Model, parts: call_parts });
+        request.messages.push(ChatMessage { role: Role::User, parts: result_parts });
+        rounds += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::mock::MockProvider;
+    use crate::llm::{EmbeddingTask, FinishReason, TokenUsage};
+    use crate::upstream::{build_http_client, CircuitBreaker, RetryPolicy};
+    use serenity::async_trait;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    fn tools() -> ToolContext {
+        ToolContext {
+            db: Arc::new(Database::unconnected()),
+            tenor: Arc::new(Upstream {
+                name: "Tenor",
+                http: build_http_client(Duration::from_secs(1)),
+                api_key: String::new(),
+                retry: RetryPolicy { max_retries: 0, base_delay: Duration::ZERO, max_delay: Duration::ZERO },
+                breaker: CircuitBreaker::new(1, Duration::from_secs(60)),
+            }),
+            user_id: UserId(1),
+        }
+    }
+
+    fn response(text: &str, tool_calls: Vec<ToolCall>) -> LlmResponse {
+        LlmResponse { text: text.to_string(), tool_calls, finish_reason: Some(FinishReason::Stop), usage: TokenUsage { prompt_tokens: 10, response_tokens: 1 } }
+    }
+
+    /// Asks for the Berlin time until it has asked `tool_rounds` times (or tools are
+    /// switched off), then answers. Keeps every request it got.
+    struct TimeAsker {
+        tool_rounds: usize,
+        requests: Mutex<Vec<LlmRequest>>,
+    }
+
+    #[async_trait]
+    impl LlmProvider for TimeAsker {
+        fn name(&self) -> String {
+            "time-asker".to_string()
+        }
+
+        async fn generate(&self, request: &LlmRequest) -> Result<LlmResponse, LlmError> {
+            let mut requests = self.requests.lock().unwrap();
+            requests.push(request.clone());
+            if requests.len() > self.tool_rounds || request.tool_choice == ToolChoice::None {
+                return Ok(response("It's fox o'clock.", Vec::new()));
+            }
+            Ok(response("", vec![ToolCall { id: Some("call".to_string()), name: "get_berlin_time".to_string(), args: json!({}) }]))
+        }
+
+        async fn embed(&self, _text: &str, _task: EmbeddingTask) -> Result<Vec<f32>, LlmError> {
+            Err(LlmError::Decode("not supported".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn text_answers_come_back_after_one_round() {
+        let answer = generate_with_tools(&MockProvider, LlmRequest { messages: vec![ChatMessage::user("hi")], ..Default::default() }, &tools()).await.unwrap();
+        assert_eq!(answer.text, "[mock reply to 1 message(s), 0 attachment(s)] hi");
+        assert!(answer.tool_calls.is_empty());
+    }
+
+    #[tokio::test]
+    async fn tool_results_are_sent_back_and_usage_adds_up() {
+        let provider = TimeAsker { tool_rounds: 1, requests: Mutex::new(Vec::new()) };
+        let answer = generate_with_tools(&provider, LlmRequest { messages: vec![ChatMessage::user("what time is it?")], ..Default::default() }, &tools()).await.unwrap();
+
+        assert_eq!(answer.text, "It's fox o'clock.");
+        assert_eq!(answer.usage.prompt_tokens, 20);
+        let requests = provider.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].tools.len(), declarations().len());
+        let last = requests[1].messages.last().unwrap();
+        assert_eq!(last.role, Role::User);
+        assert!(matches!(&last.parts[0], ChatPart::ToolResult { name, result, .. } if name == "get_berlin_time" && result.get("time").is_some()));
+    }
+
+    #[tokio::test]
+    async fn tools_are_switched_off_after_the_last_round() {
+        let provider = TimeAsker { tool_rounds: usize::MAX, requests: Mutex::new(Vec::new()) };
+        let answer = generate_with_tools(&provider, LlmRequest { messages: vec![ChatMessage::user("loop")], ..Default::default() }, &tools()).await.unwrap();
+
+        assert_eq!(answer.text, "It's fox o'clock.");
+        let requests = provider.requests.lock().unwrap();
+        assert_eq!(requests.len(), MAX_TOOL_ROUNDS + 1);
+        assert_eq!(requests.last().unwrap().tool_choice, ToolChoice::None);
+    }
+}
@@ -0,0 +1,121 @@
+//! `/translate` and the "Translate to my language" message command. The model is asked for
+//! structured output so the detected source language can be shown with the translation.
+
+use crate::call_llm_structured;
+use crate::llm::{LlmError, LlmProvider};
+use crate::prompts::Prompts;
+use crate::safety;
+use crate::usage::Caller;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use serenity::client::Context;
+use serenity::model::application::interaction::autocomplete::AutocompleteInteraction;
+
+/// Name of the message context menu command.
+pub const CONTEXT_MENU: &str = "Translate to my language";
+/// Discord shows at most this many autocomplete choices.
+const MAX_CHOICES: usize = 25;
+
+/// Languages offered by autocomplete, most used first. Any other language can still be typed.
+const LANGUAGES: &[&str] = &[
+    "English", "German", "French", "Spanish", "Italian", "Portuguese", "Dutch", "Polish",
+    "Russian", "Ukrainian", "Japanese", "Korean", "Chinese (Simplified)", "Chinese (Traditional)",
+    "Turkish", "Swedish", "Norwegian", "Danish", "Finnish", "Icelandic", "Czech", "Slovak",
+    "Hungarian", "Romanian", "Bulgarian", "Croatian", "Serbian", "Slovenian", "Greek",
+    "Lithuanian", "Latvian", "Estonian", "Irish", "Welsh", "Catalan", "Basque", "Arabic",
+    "Hebrew", "Persian", "Hindi", "Bengali", "Urdu", "Thai", "Vietnamese", "Indonesian",
+    "Malay", "Tagalog", "Swahili", "Latin", "Ancient Greek", "Old Norse", "Esperanto",
+];
+
+/// Discord's locales and the language each one stands for.
+const LOCALES: &[(&str, &str)] = &[
+    ("id", "Indonesian"), ("da", "Danish"), ("de", "German"), ("en-GB", "English"),
+    ("en-US", "English"), ("es-ES", "Spanish"), ("es-419", "Spanish"), ("fr", "French"),
+    ("hr", "Croatian"), ("it", "Italian"), ("lt", "Lithuanian"), ("hu", "Hungarian"),
+    ("nl", "Dutch"), ("no", "Norwegian"), ("pl", "Polish"), ("pt-BR", "Portuguese"),
+    ("ro", "Romanian"), ("fi", "Finnish"), ("sv-SE", "Swedish"), ("vi", "Vietnamese"),
+    ("tr", "Turkish"), ("cs", "Czech"), ("el", "Greek"), ("bg", "Bulgarian"),
+    ("ru", "Russian"), ("uk", "Ukrainian"), ("hi", "Hindi"), ("th", "Thai"),
+    ("zh-CN", "Chinese (Simplified)"), ("ja", "Japanese"), ("zh-TW", "Chinese (Traditional)"),
+    ("ko", "Korean"),
+];
+
+#[derive(Deserialize, Debug)]
+pub struct Translation {
+    #[serde(default)]
+    pub source_language: Option<String>,
+    pub translation: String,
+}
+
+fn schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "source_language": { "type": "string", "description": "The language the original text is written in, in English, e.g. \"German\"." },
+            "translation": { "type": "string", "description": "The translated text." }
+        },
+        "required": ["source_language", "translation"]
+    })
+}
+
+/// The language of a Discord locale such as "de" or "pt-BR", English for unknown ones.
+pub fn language_for_locale(locale: &str) -> &'static str {
+    LOCALES.iter().find(|(code, _)| *code == locale).map_or("English", |(_, language)| language)
+}
+
+/// Translates `text` into `language`. A backend that ignores the schema and answers with
+/// plain text still gets its answer shown, without a source language.
+pub async fn translate(provider: &dyn LlmProvider, caller: &Caller, prompts: &Prompts, text: &str, language: &str) -> Result<Translation, LlmError> {
+    let prompt = prompts.render("translate", &[
+        ("language", &safety::single_line(language, 50)),
+        ("text", &safety::delimit_user_text(text)),
+    ]);
+    let answer = call_llm_structured(provider, caller, None, &prompt, schema()).await?;
+    Ok(serde_json::from_str::<Translation>(&answer).unwrap_or_else(|e| {
+        eprintln!("[WARN] Translation wasn't the expected JSON ({}), showing it as plain text.", e);
+        Translation { source_language: None, translation: answer }
+    }))
+}
+
+/// "**German → English**" followed by the translation.
+pub fn render(translation: &Translation, language: &str) -> String {
+    let target = safety::single_line(language, 50);
+    match translation.source_language.as_deref().map(str::trim).filter(|source| !source.is_empty()) {
+        Some(source) => format!("**{} → {}**\n{}", safety::single_line(source, 50), target, translation.translation),
+        None => format!("**→ {}**\n{}", target, translation.translation),
+    }
+}
+
+/// Languages matching what has been typed so far: those starting with it first, then those
+/// containing it. Text that isn't a known language is offered as it is.
+fn matching_languages(typed: &str) -> Vec<String> {
+    let typed = typed.trim();
+    let lower = typed.to_lowercase();
+    let mut choices: Vec<String> = LANGUAGES.iter().filter(|l| l.to_lowercase().starts_with(&lower))
+        .chain(LANGUAGES.iter().filter(|l| !l.to_lowercase().starts_with(&lower) && l.to_lowercase().contains(&lower)))
+        .map(|l| l.to_string())
+        .collect();
+    if !typed.is_empty() && !choices.iter().any(|c| c.eq_ignore_ascii_case(typed)) {
+        choices.insert(0, safety::single_line(typed, 100));
+    }
+    choices.truncate(MAX_CHOICES);
+    choices
+}
+
+/// Suggests languages for `/translate`'s `language` option.
+pub async fn handle_autocomplete(ctx: &Context, autocomplete: &AutocompleteInteraction) {
+    let typed = autocomplete.data.options.iter()
+        .find(|opt| opt.focused && opt.name == "language")
+        .and_then(|opt| opt.value.as_ref())
+        .and_then(|v| v.as_str())
+        .unwrap_or("");
+    let choices = matching_languages(typed);
+    if let Err(e) = autocomplete.create_autocomplete_response(&ctx.http, |response| {
+        for choice in &choices {
+            response.add_string_choice(choice, choice);
+        }
+        response
+    }).await {
+        eprintln!("[ERROR] Could not send language suggestions: {:?}", e);
+    }
+}
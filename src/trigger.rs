@@ -0,0 +1,173 @@
+//! Deciding whether a message is talking to Nuggies: an @mention of the bot, or her name or
+//! one of its aliases (`NUGGIES_ALIASES`) as a whole word. Code blocks, inline code and
+//! quotes are ignored, so pasted logs and quoted messages don't set her off. Admins can turn
+//! the name trigger off per channel with `/channel keyword`; mentions always work.
+
+use crate::Database;
+use regex::Regex;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use std::sync::OnceLock;
+use tokio_postgres::types::ToSql;
+
+/// How a message addressed Nuggies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Addressed {
+    Mention,
+    Name,
+}
+
+pub struct TriggerMatcher {
+    names: Regex,
+}
+
+impl TriggerMatcher {
+    /// "nuggies" always counts; `aliases` are matched the same way, ignoring case.
+    pub fn new(aliases: &[String]) -> Self {
+        let mut names = vec![regex::escape("nuggies")];
+        names.extend(aliases.iter().map(|alias| alias.trim()).filter(|alias| !alias.is_empty()).map(regex::escape));
+        TriggerMatcher {
+            names: Regex::new(&format!(r"(?i)\b(?:{})\b", names.join("|"))).expect("escaped names form a valid regex"),
+        }
+    }
+
+    /// Whether `content` mentions the bot or says its name, outside of code and quotes.
+    pub fn addressed(&self, content: &str, bot_id: Option<UserId>) -> Option<Addressed> {
+        let text = strip_code_and_quotes(content);
+        if bot_id.is_some_and(|id| text.contains(&format!("<@{}>", id.0)) || text.contains(&format!("<@!{}>", id.0))) {
+            Some(Addressed::Mention)
+        } else if self.names.is_match(&text) {
+            Some(Addressed::Name)
+        } else {
+            None
+        }
+    }
+}
+
+/// `content` without code blocks, inline code, block quotes (`>>> ` to the end) and quoted
+/// lines (`> `).
+fn strip_code_and_quotes(content: &str) -> String {
+    static PATTERNS: OnceLock<[Regex; 4]> = OnceLock::new();
+    let patterns = PATTERNS.get_or_init(|| [
+        Regex::new(r"(?s)```.*?```").expect("valid regex"),
+        Regex::new(r"(?ms)^>>> .*").expect("valid regex"),
+        Regex::new(r"(?m)^> .*$").expect("valid regex"),
+        Regex::new(r"`[^`\n]*`").expect("valid regex"),
+    ]);
+    patterns.iter().fold(content.to_string(), |text, pattern| pattern.replace_all(&text, " ").into_owned())
+}
+
+/// Replaces mentions of the bot with its name, so the model reads "@Nuggies" instead of an ID.
+pub fn name_bot_mentions(content: &str, bot_id: Option<UserId>) -> String {
+    match bot_id {
+        Some(id) => content.replace(&format!("<@!{}>", id.0), "@Nuggies").replace(&format!("<@{}>", id.0), "@Nuggies"),
+        None => content.to_string(),
+    }
+}
+
+/// Whether saying Nuggies' name gets an answer in the channel. On by default and when the
+/// database can't be reached.
+pub async fn keyword_enabled(db: &Database, channel_id: ChannelId) -> bool {
+    let conn = match db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection for keyword trigger lookup: {:?}", e);
+            return true;
+        }
+    };
+    let channel_id_i64 = channel_id.0 as i64;
+    match conn.query_opt("SELECT 1 FROM keyword_optout_channels WHERE channel_id = $1", &[&channel_id_i64]).await {
+        Ok(row) => row.is_none(),
+        Err(e) => {
+            eprintln!("[ERROR] Failed to load keyword trigger setting for channel (ID: {}): {:?}", channel_id, e);
+            true
+        }
+    }
+}
+
+/// Turns the name trigger on or off for a channel, returning the reply for the admin.
+pub async fn save_keyword_setting(db: &Database, guild_id: GuildId, channel_id: ChannelId, enabled: bool, updated_by: u64) -> String {
+    let conn = match db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection to save keyword trigger setting: {:?}", e);
+            return "Sorry, I couldn't save that right now.".to_string();
+        }
+    };
+    let channel_id_i64 = channel_id.0 as i64;
+    let result = if enabled {
+        conn.execute("DELETE FROM keyword_optout_channels WHERE channel_id = $1", &[&channel_id_i64]).await
+    } else {
+        let guild_id_i64 = guild_id.0 as i64;
+        let updated_by_i64 = updated_by as i64;
+        let params: &[&(dyn ToSql + Sync)] = &[&channel_id_i64, &guild_id_i64, &updated_by_i64];
+        conn.execute(
+            "INSERT INTO keyword_optout_channels (channel_id, guild_id, updated_by, updated_at) VALUES ($1, $2, $3, NOW())
+             ON CONFLICT (channel_id) DO UPDATE SET updated_by = EXCLUDED.updated_by, updated_at = NOW()",
+            params,
+        ).await
+    };
+
+    match result {
+        Ok(_) => {
+            println!("[ACTION] User (ID: {}) turned the keyword trigger {} for channel (ID: {}).", updated_by, if enabled { "on" } else { "off" }, channel_id);
+            if enabled {
+                "Done. Saying my name in this channel gets an answer again.".to_string()
+            } else {
+                "Done. I'll ignore my name in this channel. Mentions and replies to me still work.".to_string()
+            }
+        }
+        Err(e) => {
+            eprintln!("[ERROR] Failed to save keyword trigger setting for channel (ID: {}): {:?}", channel_id, e);
+            "Sorry, I couldn't save that right now.".to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOT: UserId = UserId(42);
+
+    fn matcher() -> TriggerMatcher {
+        TriggerMatcher::new(&["Nugs".to_string(), " ".to_string()])
+    }
+
+    #[test]
+    fn the_name_counts_only_as_a_whole_word() {
+        let matcher = matcher();
+        assert_eq!(matcher.addressed("hey nuggies, how are you?", Some(BOT)), Some(Addressed::Name));
+        assert_eq!(matcher.addressed("NUGGIES!", Some(BOT)), Some(Addressed::Name));
+        assert_eq!(matcher.addressed("is nuggiesbot down again?", Some(BOT)), None);
+        assert_eq!(matcher.addressed("ask supernuggies", Some(BOT)), None);
+    }
+
+    #[test]
+    fn aliases_match_regardless_of_case() {
+        let matcher = matcher();
+        assert_eq!(matcher.addressed("thanks nugs", Some(BOT)), Some(Addressed::Name));
+        assert_eq!(matcher.addressed("NuGs what do you think", Some(BOT)), Some(Addressed::Name));
+        assert_eq!(matcher.addressed("nugsy", Some(BOT)), None);
+        // Blank aliases are ignored rather than matching every space.
+        assert_eq!(matcher.addressed("just chatting", Some(BOT)), None);
+    }
+
+    #[test]
+    fn both_mention_forms_count() {
+        let matcher = matcher();
+        assert_eq!(matcher.addressed("<@42> hi", Some(BOT)), Some(Addressed::Mention));
+        assert_eq!(matcher.addressed("hi <@!42>", Some(BOT)), Some(Addressed::Mention));
+        assert_eq!(matcher.addressed("<@43> hi", Some(BOT)), None);
+        assert_eq!(matcher.addressed("<@42> hi", None), None);
+    }
+
+    #[test]
+    fn code_and_quotes_are_ignored() {
+        let matcher = matcher();
+        assert_eq!(matcher.addressed("```rust\nlet nuggies = 1;\n```", Some(BOT)), None);
+        assert_eq!(matcher.addressed("run `nuggies --help`", Some(BOT)), None);
+        assert_eq!(matcher.addressed("> nuggies said hi\nlol", Some(BOT)), None);
+        assert_eq!(matcher.addressed(">>> nuggies\nstill quoted <@42>", Some(BOT)), None);
+        assert_eq!(matcher.addressed("> quoted\nnuggies, you there?", Some(BOT)), Some(Addressed::Name));
+    }
+}
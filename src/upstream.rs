@@ -0,0 +1,240 @@
+//! Shared plumbing for the external HTTP APIs the bot depends on (Gemini, Tenor):
+//! one pooled client with timeouts, retries with exponential backoff, and a circuit
+//! breaker per API so an outage doesn't make every command hang.
+
+use rand::Rng;
+use reqwest::{Client as HttpClient, RequestBuilder, Response, StatusCode};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub fn build_http_client(timeout: Duration) -> HttpClient {
+    HttpClient::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .timeout(timeout)
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with up to 50% random jitter, capped at `max_delay`.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self.base_delay.saturating_mul(2u32.saturating_pow(attempt));
+        let jitter = rand::thread_rng().gen_range(0.0..0.5);
+        exponential.mul_f64(1.0 + jitter).min(self.max_delay)
+    }
+}
+
+enum BreakerState {
+    Closed { consecutive_failures: u32 },
+    Open { until: Instant },
+    HalfOpen,
+}
+
+/// Opens after `failure_threshold` consecutive failed requests and rejects calls for
+/// `cooldown`. After that a single trial request is let through: success closes the
+/// breaker again, failure re-opens it.
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            failure_threshold,
+            cooldown,
+            state: Mutex::new(BreakerState::Closed { consecutive_failures: 0 }),
+        }
+    }
+
+    /// `Err` holds how long until the breaker lets requests through again. The outcome of
+    /// the request is reported through the returned `Permit`.
+    fn allow(&self) -> Result<Permit<'_>, Duration> {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            BreakerState::Closed { .. } => Ok(Permit { breaker: self, trial: false, resolved: false }),
+            BreakerState::Open { until } => {
+                let now = Instant::now();
+                if now >= until {
+                    *state = BreakerState::HalfOpen;
+                    Ok(Permit { breaker: self, trial: true, resolved: false })
+                } else {
+                    Err(until - now)
+                }
+            }
+            // A trial request is already in flight.
+            BreakerState::HalfOpen => Err(Duration::from_secs(1)),
+        }
+    }
+
+    fn record_success(&self) {
+        *self.state.lock().unwrap() = BreakerState::Closed { consecutive_failures: 0 };
+    }
+
+    /// Returns true if this failure tripped the breaker.
+    fn record_failure(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let failures = match *state {
+            BreakerState::Closed { consecutive_failures } => consecutive_failures + 1,
+            BreakerState::HalfOpen => self.failure_threshold,
+            BreakerState::Open { .. } => return false,
+        };
+        if failures >= self.failure_threshold {
+            *state = BreakerState::Open { until: Instant::now() + self.cooldown };
+            true
+        } else {
+            *state = BreakerState::Closed { consecutive_failures: failures };
+            false
+        }
+    }
+}
+
+/// Permission to send one request. A trial permit that is dropped without an outcome (its
+/// future was cancelled or panicked) re-opens the breaker right away, so the next request
+/// becomes the trial instead of the breaker staying half-open forever.
+struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    trial: bool,
+    resolved: bool,
+}
+
+impl Permit<'_> {
+    fn success(mut self) {
+        self.resolved = true;
+        self.breaker.record_success();
+    }
+
+    /// Returns true if this failure tripped the breaker.
+    fn failure(mut self) -> bool {
+        self.resolved = true;
+        self.breaker.record_failure()
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.trial && !self.resolved {
+            let mut state = self.breaker.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if matches!(*state, BreakerState::HalfOpen) {
+                *state = BreakerState::Open { until: Instant::now() };
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    /// The circuit breaker is open; the API is considered down for the given duration.
+    CircuitOpen { retry_in: Duration },
+    /// The last attempt failed without a response.
+    Request(reqwest::Error),
+}
+
+/// An external API: the shared HTTP client plus this API's key, retry policy and breaker.
+pub struct Upstream {
+    pub name: &'static str,
+    pub http: HttpClient,
+    pub api_key: String,
+    pub retry: RetryPolicy,
+    pub breaker: CircuitBreaker,
+}
+
+impl Upstream {
+    /// Sends the request built by `build`, retrying transport errors, 429 and 5xx responses.
+    /// The final response is returned even if it is an error status, so callers can still
+    /// inspect it; only a missing response or an open breaker is an `Err`.
+    pub async fn send<F>(&self, build: F) -> Result<Response, SendError>
+    where
+        F: Fn(&HttpClient) -> RequestBuilder,
+    {
+        let permit = match self.breaker.allow() {
+            Ok(permit) => permit,
+            Err(retry_in) => {
+                println!("[CIRCUIT - {}] Breaker is open, skipping request ({}s left).", self.name, retry_in.as_secs());
+                return Err(SendError::CircuitOpen { retry_in });
+            }
+        };
+
+        let mut attempt = 0;
+        loop {
+            let result = build(&self.http).send().await;
+
+            let delay = match &result {
+                Ok(response) if !is_retryable(response.status()) => {
+                    permit.success();
+                    return result.map_err(SendError::Request);
+                }
+                Ok(response) => retry_after(response).unwrap_or_else(|| self.retry.backoff(attempt)),
+                Err(_) => self.retry.backoff(attempt),
+            };
+
+            if attempt >= self.retry.max_retries || delay > self.retry.max_delay {
+                if permit.failure() {
+                    eprintln!("[CIRCUIT - {}] Too many failures, opening the circuit breaker.", self.name);
+                }
+                return result.map_err(SendError::Request);
+            }
+
+            match &result {
+                Ok(response) => eprintln!("[RETRY - {}] Got HTTP {}, retrying in {}ms (attempt {}/{}).", self.name, response.status(), delay.as_millis(), attempt + 1, self.retry.max_retries),
+                Err(e) => eprintln!("[RETRY - {}] Request failed ({}), retrying in {}ms (attempt {}/{}).", self.name, e, delay.as_millis(), attempt + 1, self.retry.max_retries),
+            }
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Parses a `Retry-After` header, given either in seconds or as an HTTP date.
+pub fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breaker_opens_after_threshold_and_closes_on_successful_trial() {
+        let breaker = CircuitBreaker::new(2, Duration::ZERO);
+        assert!(!breaker.allow().unwrap().failure());
+        assert!(breaker.allow().unwrap().failure());
+
+        let trial = breaker.allow().expect("cooldown is over, so a trial is let through");
+        assert!(breaker.allow().is_err(), "only one trial at a time");
+        trial.success();
+        assert!(breaker.allow().is_ok());
+    }
+
+    #[test]
+    fn dropped_trial_does_not_wedge_the_breaker() {
+        let breaker = CircuitBreaker::new(1, Duration::ZERO);
+        assert!(breaker.allow().unwrap().failure());
+
+        drop(breaker.allow().expect("trial"));
+        assert!(breaker.allow().is_ok(), "a cancelled trial lets the next request try again");
+    }
+
+    #[test]
+    fn dropped_permit_while_closed_is_not_a_failure() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+        drop(breaker.allow().unwrap());
+        assert!(breaker.allow().is_ok());
+    }
+}
@@ -0,0 +1,184 @@
+//! Token usage accounting. Every AI call records how many prompt and response tokens it
+//! used, per user, guild and command, and is refused once the user or guild has used up
+//! its daily quota. Days start at midnight Berlin time, like `/daily`.
+
+use crate::config::BotConfig;
+use crate::llm::{LlmError, TokenUsage};
+use crate::{BotConfigKey, Database, DatabaseKey};
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Europe::Berlin;
+use serenity::model::id::{GuildId, UserId};
+use serenity::prelude::TypeMap;
+use std::sync::Arc;
+use tokio_postgres::types::ToSql;
+
+/// Who an AI call is made for, and through which command.
+pub struct Caller {
+    pub db: Arc<Database>,
+    pub config: Arc<BotConfig>,
+    pub user_id: UserId,
+    pub guild_id: Option<GuildId>,
+    pub command: &'static str,
+    /// False for features a server turned on rather than something the user asked for, like
+    /// auto-translation: the tokens count against the guild's quota only.
+    pub counts_for_user: bool,
+}
+
+impl Caller {
+    pub fn new(data: &TypeMap, user_id: UserId, guild_id: Option<GuildId>, command: &'static str) -> Self {
+        Caller {
+            db: data.get::<DatabaseKey>().expect("Expected DatabaseKey in TypeMap.").clone(),
+            config: data.get::<BotConfigKey>().expect("Expected BotConfigKey in TypeMap.").clone(),
+            user_id,
+            guild_id,
+            command,
+            counts_for_user: true,
+        }
+    }
+
+    /// A call charged to the guild only. `user_id` is still recorded, but doesn't use up
+    /// that user's allowance.
+    pub fn for_guild(data: &TypeMap, user_id: UserId, guild_id: GuildId, command: &'static str) -> Self {
+        Caller { counts_for_user: false, ..Caller::new(data, user_id, Some(guild_id), command) }
+    }
+}
+
+/// Midnight in Berlin, as UTC.
+fn start_of_today() -> DateTime<Utc> {
+    let today = Utc::now().with_timezone(&Berlin).date_naive();
+    let midnight = today.and_hms_opt(0, 0, 0).expect("midnight is a valid time");
+    Berlin.from_local_datetime(&midnight).earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .unwrap_or_else(Utc::now)
+}
+
+/// Tokens used and requests made since `since`, for one user or one guild. A user's totals
+/// leave out calls charged to the guild only.
+async fn totals(db: &Database, column: &str, id: u64, since: DateTime<Utc>) -> Result<(i64, i64), String> {
+    let conn = db.pool.get().await.map_err(|e| format!("{:?}", e))?;
+    let id_i64 = id as i64;
+    let params: &[&(dyn ToSql + Sync)] = &[&id_i64, &since];
+    let filter = if column == "user_id" { " AND counts_for_user" } else { "" };
+    let query = format!(
+        "SELECT COALESCE(SUM(prompt_tokens + response_tokens), 0)::BIGINT, COUNT(*) FROM llm_usage WHERE {} = $1 AND created_at >= $2{}",
+        column, filter
+    );
+    let row = conn.query_one(query.as_str(), params).await.map_err(|e| format!("{:?}", e))?;
+    Ok((row.get(0), row.get(1)))
+}
+
+/// Refuses the call if the user or the guild has reached its daily token quota. A quota
+/// of 0 means unlimited. If the totals can't be read, the call is let through.
+pub async fn check_quota(caller: &Caller) -> Result<(), LlmError> {
+    let since = start_of_today();
+
+    if caller.counts_for_user && caller.config.daily_user_token_quota > 0 {
+        match totals(&caller.db, "user_id", caller.user_id.0, since).await {
+            Ok((used, _)) if used as u64 >= caller.config.daily_user_token_quota => {
+                println!("[QUOTA] User (ID: {}) has used {} tokens today, over the quota.", caller.user_id, used);
+                return Err(LlmError::QuotaExceeded {
+                    message: "You've used up your AI allowance for today. It resets at midnight (Berlin time).".to_string(),
+                });
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("[ERROR] Failed to read token usage for User (ID: {}): {}", caller.user_id, e),
+        }
+    }
+
+    if let (Some(guild_id), true) = (caller.guild_id, caller.config.daily_guild_token_quota > 0) {
+        match totals(&caller.db, "guild_id", guild_id.0, since).await {
+            Ok((used, _)) if used as u64 >= caller.config.daily_guild_token_quota => {
+                println!("[QUOTA] Guild (ID: {}) has used {} tokens today, over the quota.", guild_id, used);
+                return Err(LlmError::QuotaExceeded {
+                    message: "This server has used up its AI allowance for today. It resets at midnight (Berlin time).".to_string(),
+                });
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("[ERROR] Failed to read token usage for Guild (ID: {}): {}", guild_id, e),
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn record(caller: &Caller, usage: TokenUsage) {
+    let conn = match caller.db.pool.get().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("[ERROR] Failed to get DB connection to record token usage: {:?}", e);
+            return;
+        }
+    };
+
+    let user_id_i64 = caller.user_id.0 as i64;
+    let guild_id_i64 = caller.guild_id.map(|id| id.0 as i64);
+    let prompt_tokens = usage.prompt_tokens as i32;
+    let response_tokens = usage.response_tokens as i32;
+    let params: &[&(dyn ToSql + Sync)] = &[&user_id_i64, &guild_id_i64, &caller.command, &prompt_tokens, &response_tokens, &caller.counts_for_user];
+    if let Err(e) = conn.execute(
+        "INSERT INTO llm_usage (user_id, guild_id, command, prompt_tokens, response_tokens, counts_for_user) VALUES ($1, $2, $3, $4, $5, $6)",
+        params,
+    ).await {
+        eprintln!("[ERROR] Failed to record token usage for User (ID: {}): {:?}", caller.user_id, e);
+    }
+}
+
+fn quota_text(quota: u64) -> String {
+    if quota == 0 { "no daily limit".to_string() } else { format!("daily limit {}", quota) }
+}
+
+/// The `/usage` report: today's and the last 30 days' totals for the user and their server.
+pub async fn summary(db: &Database, config: &BotConfig, user_id: UserId, guild_id: Option<GuildId>) -> String {
+    let today = start_of_today();
+    let month = Utc::now() - chrono::Duration::days(30);
+
+    let user_today = totals(db, "user_id", user_id.0, today).await;
+    let user_month = totals(db, "user_id", user_id.0, month).await;
+    let (user_today, user_month) = match (user_today, user_month) {
+        (Ok(today), Ok(month)) => (today, month),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("[ERROR] Failed to read token usage for User (ID: {}): {}", user_id, e);
+            return "Sorry, I couldn't look up the usage right now.".to_string();
+        }
+    };
+
+    let mut report = format!(
+        "📊 **Your AI usage**\nToday: **{}** tokens in {} requests ({})\nLast 30 days: **{}** tokens in {} requests",
+        user_today.0, user_today.1, quota_text(config.daily_user_token_quota), user_month.0, user_month.1
+    );
+
+    if let Some(guild_id) = guild_id {
+        if let (Ok(guild_today), Ok(guild_month)) = (totals(db, "guild_id", guild_id.0, today).await, totals(db, "guild_id", guild_id.0, month).await) {
+            report.push_str(&format!(
+                "\n\n🏰 **Server AI usage**\nToday: **{}** tokens in {} requests ({})\nLast 30 days: **{}** tokens in {} requests",
+                guild_today.0, guild_today.1, quota_text(config.daily_guild_token_quota), guild_month.0, guild_month.1
+            ));
+            if let Some(breakdown) = command_breakdown(db, guild_id, month).await {
+                report.push_str(&format!("\nBy command: {}", breakdown));
+            }
+        }
+    }
+    report
+}
+
+/// "/nuggies 1200 · /ask 300" for the guild's usage since `since`.
+async fn command_breakdown(db: &Database, guild_id: GuildId, since: DateTime<Utc>) -> Option<String> {
+    let conn = db.pool.get().await.ok()?;
+    let guild_id_i64 = guild_id.0 as i64;
+    let params: &[&(dyn ToSql + Sync)] = &[&guild_id_i64, &since];
+    let rows = conn.query(
+        "SELECT command, SUM(prompt_tokens + response_tokens)::BIGINT AS tokens FROM llm_usage
+         WHERE guild_id = $1 AND created_at >= $2 GROUP BY command ORDER BY tokens DESC",
+        params,
+    ).await.map_err(|e| eprintln!("[ERROR] Failed to read token usage by command: {:?}", e)).ok()?;
+
+    if rows.is_empty() {
+        return None;
+    }
+    Some(rows.iter().map(|row| {
+        let command: String = row.get(0);
+        let tokens: i64 = row.get(1);
+        // The keyword trigger isn't a slash command.
+        if command == "message" { format!("mentions {}", tokens) } else { format!("/{} {}", command, tokens) }
+    }).collect::<Vec<_>>().join(" · "))
+}